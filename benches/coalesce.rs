@@ -0,0 +1,46 @@
+//! Frames handed to the native library for a burst of small messages, with
+//! and without outbound coalescing.
+//!
+//! Each emitted frame is normally one write on the socket, so the emit count
+//! stands in for the syscalls saved. Runs against the `TestHarness`
+//! loopback, which counts emits instead of writing:
+//!
+//! ```text
+//! cargo bench --features test --bench coalesce
+//! ```
+
+use std::time::{Duration, Instant};
+use ws::TestHarness;
+
+const BURSTS: usize = 100;
+const BURST: usize = 100;
+const MESSAGE: &str = "{\"tick\":12345,\"price\":101.25}\n";
+
+/// Sends `BURSTS` bursts of `BURST` messages, flushing after each as a
+/// passing window would, and prints the frames emitted and the time taken.
+fn measure(name: &str, window: Option<Duration>) {
+    let harness = TestHarness::new();
+    let ws = harness.websocket();
+    let id = harness.connect();
+    ws.set_coalesce_window(id, window)
+        .expect("connection is open");
+
+    let start = Instant::now();
+    for _ in 0..BURSTS {
+        for _ in 0..BURST {
+            ws.send_text(id, MESSAGE).expect("send");
+        }
+        ws.flush(id).expect("flush");
+    }
+    let elapsed = start.elapsed();
+    println!(
+        "{name:<20} {:>6} emits for {} messages in {elapsed:?}",
+        harness.sent_frames().len(),
+        BURSTS * BURST,
+    );
+}
+
+fn main() {
+    measure("no coalescing", None);
+    measure("coalescing", Some(Duration::from_millis(10)));
+}
@@ -0,0 +1,84 @@
+//! CPU an idle server keeps busy: a bare `operate` loop against
+//! `operate_with_timeout` and `run`'s backoff.
+//!
+//! Each loop services a server context bound to an ephemeral port on
+//! localhost with no client, so the native library's own idle work is
+//! measured along with the wrapper's. Needs the native library to be
+//! loadable (see the README's search order):
+//!
+//! ```text
+//! cargo bench --bench idle_cpu
+//! ```
+
+#[cfg(unix)]
+use std::thread;
+use std::time::{Duration, Instant};
+#[cfg(unix)]
+use ws::{Endpoint, Settings, WebSocket, WsError};
+
+/// How long each loop runs.
+const RUN: Duration = Duration::from_secs(2);
+
+/// CPU time, user and system, used by the process so far.
+#[cfg(unix)]
+fn cpu_time() -> Duration {
+    let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+    unsafe { libc::getrusage(libc::RUSAGE_SELF, &mut usage) };
+    let time = |tv: libc::timeval| Duration::new(tv.tv_sec as u64, tv.tv_usec as u32 * 1000);
+    time(usage.ru_utime) + time(usage.ru_stime)
+}
+
+/// Runs `idle` until the deadline it is given and prints the share of a
+/// core it used.
+#[cfg(unix)]
+fn measure(name: &str, idle: impl FnOnce(Instant)) {
+    let (start, cpu) = (Instant::now(), cpu_time());
+    idle(start + RUN);
+    let busy = (cpu_time() - cpu).as_secs_f64() / start.elapsed().as_secs_f64();
+    println!("{name:<30} {:>6.1}% of a core", busy * 100.0);
+}
+
+/// Applies default server settings and listens on an ephemeral port.
+#[cfg(unix)]
+fn listen(ws: &WebSocket) -> Result<(), WsError> {
+    ws.setup(
+        &Settings::builder()
+            .endpoint(Endpoint::Server)
+            .host("localhost")
+            .build()?,
+    )?;
+    ws.bind(c"localhost", None)?;
+    Ok(())
+}
+
+#[cfg(unix)]
+fn main() {
+    let ws = WebSocket::new().expect("native library");
+    listen(&ws).expect("listen");
+
+    measure("while operate()", |deadline| {
+        while Instant::now() < deadline {
+            ws.operate();
+        }
+    });
+    measure("operate_with_timeout(1 ms)", |deadline| {
+        while Instant::now() < deadline {
+            ws.operate_with_timeout(Duration::from_millis(1));
+        }
+    });
+    drop(ws);
+
+    // `run` only returns once the context stops, so it runs on the
+    // spawned context's thread while this one sleeps.
+    let server = WebSocket::spawn(listen).expect("spawn");
+    measure("run (idle_backoff_cap 5 ms)", |deadline| {
+        thread::sleep(deadline - Instant::now());
+    });
+    server.stop();
+    server.join().expect("server thread panicked");
+}
+
+#[cfg(not(unix))]
+fn main() {
+    eprintln!("idle_cpu reads CPU time with getrusage, which needs a Unix platform");
+}
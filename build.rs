@@ -1,22 +1,126 @@
-use std::{env, path::PathBuf};
-
-fn main() {
-    println!("cargo:rerun-if-changed=submodule/Websocket/websocket/include/websocket/api/websocket_c_api.h");
-    println!("cargo:rerun-if-changed=build.rs");
-
-    let bindings = bindgen::Builder::default()
-        .header("submodule/Websocket/websocket/include/websocket/api/websocket_c_api.h")
-        .derive_default(true)
-        .clang_arg("-xc++")
-        .clang_arg("-std=c++17")
-        .clang_arg("-DWEBSOCKET_C_API")
-        .clang_arg("-DWEBSOCKET_API=")
-        .clang_arg("-Isubmodule/Websocket/websocket/include")
-        .generate()
-        .expect("Unable to generate bindings");
-
-    let out_path = PathBuf::from(env::var("OUT_DIR").unwrap());
-    bindings
-        .write_to_file(out_path.join("bindings.rs"))
-        .expect("Couldn't write bindings!");
-}
+use std::{env, fs, path::PathBuf};
+
+const NATIVE_BUILD_DIR: &str = "submodule/Websocket/build";
+const NATIVE_CMAKE_LISTS: &str = "submodule/Websocket/CMakeLists.txt";
+
+/// Candidate locations of the shared library produced by `build.sh` /
+/// `build.bat`, paired with the name it is loaded under at runtime.
+fn native_library_candidates(target_os: &str, profile: &str) -> (Vec<PathBuf>, &'static str) {
+    let build_dir = PathBuf::from(NATIVE_BUILD_DIR);
+    match target_os {
+        "windows" => {
+            let (first, second) = if profile == "release" {
+                ("release", "debug")
+            } else {
+                ("debug", "release")
+            };
+            (
+                vec![
+                    build_dir.join("bin").join(first).join("LIB_SHARED.dll"),
+                    build_dir.join("bin").join(second).join("LIB_SHARED.dll"),
+                    build_dir.join("bin").join("LIB_SHARED.dll"),
+                ],
+                "Websocket.dll",
+            )
+        }
+        "macos" => (
+            vec![build_dir.join("lib").join("libLIB_SHARED.dylib")],
+            "Websocket.dylib",
+        ),
+        _ => (
+            vec![build_dir.join("lib").join("libLIB_SHARED.so")],
+            "Websocket.so",
+        ),
+    }
+}
+
+/// Links the static library built with `-DBUILD_STATIC=ON` for the
+/// `static-link` feature, along with what it needs from the system: the C++
+/// runtime and OpenSSL.
+fn link_native_library(target_os: &str, profile: &str) {
+    let manifest_dir = PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap());
+    let lib_dir = manifest_dir.join(NATIVE_BUILD_DIR).join("lib");
+    println!("cargo:rustc-link-search=native={}", lib_dir.display());
+    if target_os == "windows" {
+        // Multi-config generators put the library in a per-configuration
+        // subdirectory.
+        for config in [profile, "release", "debug"] {
+            println!(
+                "cargo:rustc-link-search=native={}",
+                lib_dir.join(config).display()
+            );
+        }
+    }
+    println!("cargo:rustc-link-lib=static=LIB_STATIC");
+
+    let system_libs: &[&str] = match target_os {
+        "windows" => &["libssl", "libcrypto"],
+        "macos" => &["c++", "ssl", "crypto"],
+        _ => &["stdc++", "ssl", "crypto"],
+    };
+    for lib in system_libs {
+        println!("cargo:rustc-link-lib=dylib={lib}");
+    }
+}
+
+/// The `VERSION` given to `project()` in the native library's
+/// `CMakeLists.txt`, if it declares one.
+fn native_library_version() -> Option<String> {
+    let cmake = fs::read_to_string(NATIVE_CMAKE_LISTS).ok()?;
+    let start = cmake.find("project(")?;
+    let args = &cmake[start + "project(".len()..];
+    let args = &args[..args.find(')')?];
+    let mut words = args.split_ascii_whitespace();
+    words.find(|&w| w == "VERSION")?;
+    words.next().map(str::to_owned)
+}
+
+fn main() {
+    println!(
+        "cargo:rerun-if-changed=submodule/Websocket/websocket/include/websocket/api/websocket_c_api.h"
+    );
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-changed={NATIVE_BUILD_DIR}");
+    println!("cargo:rerun-if-changed={NATIVE_CMAKE_LISTS}");
+
+    if let Some(version) = native_library_version() {
+        println!("cargo:rustc-env=WEBSOCKET_NATIVE_VERSION={version}");
+    }
+
+    let bindings = bindgen::Builder::default()
+        .header("submodule/Websocket/websocket/include/websocket/api/websocket_c_api.h")
+        .derive_default(true)
+        .clang_arg("-xc++")
+        .clang_arg("-std=c++17")
+        .clang_arg("-DWEBSOCKET_C_API")
+        .clang_arg("-DWEBSOCKET_API=")
+        .clang_arg("-Isubmodule/Websocket/websocket/include")
+        // Only the public C API; everything else reachable through the C++
+        // headers is noise.
+        .allowlist_function("websocket_.*")
+        .allowlist_type("ws_.*|e_ws_.*")
+        .allowlist_var("WS_.*|WEBSOCKET_.*|e_ws_.*")
+        .generate()
+        .expect("Unable to generate bindings");
+
+    let out_path = PathBuf::from(env::var("OUT_DIR").unwrap());
+    bindings
+        .write_to_file(out_path.join("bindings.rs"))
+        .expect("Couldn't write bindings!");
+
+    let target_os = env::var("CARGO_CFG_TARGET_OS").unwrap_or_default();
+    let profile = env::var("PROFILE").unwrap_or_default();
+    if env::var_os("CARGO_FEATURE_STATIC_LINK").is_some() {
+        link_native_library(&target_os, &profile);
+        return;
+    }
+
+    // Copy the native library into OUT_DIR, if it has been built, so the
+    // runtime loader can find it without it being copied next to the binary.
+    let (candidates, lib_name) = native_library_candidates(&target_os, &profile);
+    if let Some(src) = candidates.iter().find(|p| p.is_file()) {
+        let dest = out_path.join(lib_name);
+        fs::copy(src, &dest).expect("Couldn't copy native library");
+        println!("cargo:rustc-env=WEBSOCKET_LIB_DIR={}", out_path.display());
+    }
+}
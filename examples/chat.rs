@@ -0,0 +1,49 @@
+//! Broadcasts every text message to all connected clients.
+//!
+//! Messages are collected by the handler and broadcast from the event loop,
+//! which owns the connection registry behind `broadcast_text`.
+//!
+//! Run with `cargo run --example chat --no-default-features --features server`.
+
+use std::sync::mpsc;
+use std::time::Duration;
+use ws::{Endpoint, Opcode, OperateOutcome, Settings, WebSocket};
+
+type ResultE<T> = Result<T, Box<dyn std::error::Error>>;
+
+fn main() -> ResultE<()> {
+    let settings = Settings::builder()
+        .endpoint(Endpoint::Server)
+        .host("localhost:4433")
+        .build()?;
+
+    let ws = WebSocket::new()?;
+    let (tx, rx) = mpsc::channel::<String>();
+
+    ws.on_open(|conn, addr| {
+        let peer = addr.map_or_else(|| "<null>".to_owned(), |a| a.to_string());
+        println!("[join] fd={} addr={peer}", conn.id());
+    });
+    ws.on_close(|id, status, _| println!("[leave] fd={id} status={status:?}"));
+    ws.on_message(move |conn, opcode, data| {
+        if opcode != Opcode::Text {
+            return;
+        }
+        if let Ok(txt) = core::str::from_utf8(data) {
+            let _ = tx.send(format!("{}: {txt}", conn.id()));
+        }
+    });
+    ws.on_error(|msg| eprintln!("[error] {msg}"));
+
+    ws.setup(&settings)?;
+    let port = ws.bind(c"localhost", Some(4433))?;
+    println!("chat server listening on port {port}…");
+
+    while ws.operate_with_timeout(Duration::from_millis(5)) != OperateOutcome::Stopped {
+        for line in rx.try_iter() {
+            let sent = ws.broadcast_text(&line)?;
+            println!("[chat] {line} (to {sent} clients)");
+        }
+    }
+    Ok(())
+}
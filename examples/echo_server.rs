@@ -0,0 +1,44 @@
+//! Echoes every text and binary frame back to the client that sent it.
+//!
+//! Run with `cargo run --example echo_server --no-default-features --features server`.
+
+use ws::{Endpoint, Opcode, Settings, WebSocket};
+
+type ResultE<T> = Result<T, Box<dyn std::error::Error>>;
+
+fn main() -> ResultE<()> {
+    let settings = Settings::builder()
+        .endpoint(Endpoint::Server)
+        .host("localhost:4433")
+        .build()?;
+
+    let ws = WebSocket::new()?;
+    ws.on_open(|conn, addr| {
+        let peer = addr.map_or_else(|| "<null>".to_owned(), |a| a.to_string());
+        println!("[open] fd={} addr={peer}", conn.id());
+    });
+    ws.on_close(|id, status, reason| {
+        println!("[close] fd={id} status={status:?} reason={reason:?}")
+    });
+    ws.on_frame(|conn, opcode, data| {
+        let echoed = match opcode {
+            Opcode::Text => match core::str::from_utf8(data) {
+                Ok(txt) => conn.send_text(txt),
+                Err(_) => conn.send_binary(data),
+            },
+            Opcode::Binary => conn.send_binary(data),
+            _ => return,
+        };
+        if let Err(e) = echoed {
+            eprintln!("[error] fd={} {e}", conn.id());
+        }
+    });
+    ws.on_error(|msg| eprintln!("[error] {msg}"));
+
+    ws.setup(&settings)?;
+    let port = ws.bind(c"localhost", Some(4433))?;
+    println!("echo server listening on port {port}…  Ctrl+C to stop");
+
+    ws.run_until_ctrl_c()?;
+    Ok(())
+}
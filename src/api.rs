@@ -0,0 +1,278 @@
+use crate::sys::*;
+use crate::{ConnectionId, Opcode, Version, WsError, WsStatus};
+use std::ffi::{CStr, c_char, c_int, c_uchar, c_void};
+use std::sync::OnceLock;
+#[cfg(not(feature = "static-link"))]
+use {crate::find_library, libloading::Library, std::io, std::path::Path};
+
+/// Loads the shared library at `path`.
+///
+/// On Windows the library's own directory is searched for its dependencies
+/// first, the per-load equivalent of `AddDllDirectory`, so DLLs shipped next
+/// to it (OpenSSL, for instance) resolve without being on `PATH`.
+#[cfg(all(windows, not(feature = "static-link")))]
+unsafe fn open_library(path: &Path) -> Result<Library, libloading::Error> {
+    use libloading::os::windows::{
+        self, LOAD_LIBRARY_SEARCH_DEFAULT_DIRS, LOAD_LIBRARY_SEARCH_DLL_LOAD_DIR,
+    };
+
+    // LOAD_LIBRARY_SEARCH_DLL_LOAD_DIR requires a fully qualified path.
+    let path = std::path::absolute(path).unwrap_or_else(|_| path.to_owned());
+    let flags = LOAD_LIBRARY_SEARCH_DLL_LOAD_DIR | LOAD_LIBRARY_SEARCH_DEFAULT_DIRS;
+    match unsafe { windows::Library::load_with_flags(&path, flags) } {
+        Ok(lib) => Ok(lib.into()),
+        // Those flags leave out PATH; retry with the standard search order.
+        Err(_) => unsafe { Library::new(&path) },
+    }
+}
+
+#[cfg(not(any(windows, feature = "static-link")))]
+unsafe fn open_library(path: &Path) -> Result<Library, libloading::Error> {
+    unsafe { Library::new(path) }
+}
+
+/// Whether a load failed because a library the native library depends on
+/// is missing, rather than the native library itself.
+///
+/// Only Windows reports this distinctly (`ERROR_MOD_NOT_FOUND` for a file
+/// that exists); `dlopen` already names the missing dependency in its error.
+#[cfg(not(feature = "static-link"))]
+fn missing_dependency(source: &libloading::Error) -> bool {
+    const ERROR_MOD_NOT_FOUND: i32 = 126;
+    cfg!(windows)
+        && std::error::Error::source(source)
+            .and_then(|e| e.downcast_ref::<io::Error>())
+            .and_then(io::Error::raw_os_error)
+            == Some(ERROR_MOD_NOT_FOUND)
+}
+
+/// Function pointers resolved from the native library.
+///
+/// The `Library` is kept alongside the pointers so they never outlive it.
+/// With the `static-link` feature the pointers are the linked functions
+/// themselves and there is no `Library`; neither is there for the test
+/// harness's in-memory table.
+pub(crate) struct Api {
+    pub(crate) create: unsafe extern "C" fn() -> *mut c_void,
+    pub(crate) destroy: unsafe extern "C" fn(*mut c_void),
+    pub(crate) setup: unsafe extern "C" fn(*mut c_void, *const ws_settings_t) -> e_ws_status,
+    pub(crate) operate: unsafe extern "C" fn(*mut c_void) -> bool,
+    pub(crate) bind:
+        unsafe extern "C" fn(*mut c_void, *const c_char, *const c_char, *mut c_int) -> e_ws_status,
+    pub(crate) open:
+        unsafe extern "C" fn(*mut c_void, *const c_char, *const c_char, *mut c_int) -> e_ws_status,
+    pub(crate) on: unsafe extern "C" fn(*mut c_void, *const c_char, *mut c_void) -> e_ws_status,
+    pub(crate) frame_create: unsafe extern "C" fn(e_ws_frame_opcode) -> *mut c_void,
+    pub(crate) frame_push: unsafe extern "C" fn(*mut c_void, *const c_uchar, usize) -> bool,
+    pub(crate) frame_emit: unsafe extern "C" fn(*mut c_void, c_int, *mut c_void) -> bool,
+    pub(crate) frame_destroy: unsafe extern "C" fn(*mut c_void),
+    /// `websocket_version`, which older native builds do not export.
+    pub(crate) version: Option<unsafe extern "C" fn() -> *const c_char>,
+    #[cfg(not(feature = "static-link"))]
+    pub(crate) _lib: Option<Library>,
+}
+
+impl Api {
+    #[cfg(not(feature = "static-link"))]
+    unsafe fn load() -> Result<Self, WsError> {
+        let path = find_library()?;
+        let lib = unsafe { open_library(&path) }.map_err(|source| {
+            if missing_dependency(&source) {
+                WsError::LibraryDependency { path, source }
+            } else {
+                WsError::LibraryLoad { path, source }
+            }
+        })?;
+        unsafe {
+            Ok(Api {
+                create: symbol(&lib, "websocket_create")?,
+                destroy: symbol(&lib, "websocket_destroy")?,
+                setup: symbol(&lib, "websocket_setup")?,
+                operate: symbol(&lib, "websocket_operate")?,
+                bind: symbol(&lib, "websocket_bind")?,
+                open: symbol(&lib, "websocket_open")?,
+                on: symbol(&lib, "websocket_on")?,
+                frame_create: symbol(&lib, "websocket_frame_create")?,
+                frame_push: symbol(&lib, "websocket_frame_push")?,
+                frame_emit: symbol(&lib, "websocket_frame_emit")?,
+                frame_destroy: symbol(&lib, "websocket_frame_destroy")?,
+                version: symbol(&lib, "websocket_version").ok(),
+                _lib: Some(lib),
+            })
+        }
+    }
+}
+
+#[cfg(feature = "static-link")]
+impl Api {
+    unsafe fn load() -> Result<Self, WsError> {
+        use linked::*;
+        Ok(Api {
+            create: websocket_create,
+            destroy: websocket_destroy,
+            setup: websocket_setup,
+            operate: websocket_operate,
+            bind: websocket_bind,
+            open: websocket_open,
+            on: websocket_on,
+            frame_create: websocket_frame_create,
+            frame_push: websocket_frame_push,
+            frame_emit: websocket_frame_emit,
+            frame_destroy: websocket_frame_destroy,
+            // An optional symbol cannot be declared for static linking.
+            version: None,
+        })
+    }
+}
+
+/// The C API as linked by `build.rs` for the `static-link` feature.
+///
+/// Declared with the same pointer types as [`Api`] rather than taken from
+/// the bindgen output, so both modes share one table.
+#[cfg(feature = "static-link")]
+#[allow(clashing_extern_declarations)]
+mod linked {
+    use super::*;
+
+    unsafe extern "C" {
+        pub(super) fn websocket_create() -> *mut c_void;
+        pub(super) fn websocket_destroy(ctx: *mut c_void);
+        pub(super) fn websocket_setup(
+            ctx: *mut c_void,
+            settings: *const ws_settings_t,
+        ) -> e_ws_status;
+        pub(super) fn websocket_operate(ctx: *mut c_void) -> bool;
+        pub(super) fn websocket_bind(
+            ctx: *mut c_void,
+            host: *const c_char,
+            port: *const c_char,
+            out_port: *mut c_int,
+        ) -> e_ws_status;
+        pub(super) fn websocket_open(
+            ctx: *mut c_void,
+            host: *const c_char,
+            port: *const c_char,
+            out_port: *mut c_int,
+        ) -> e_ws_status;
+        pub(super) fn websocket_on(
+            ctx: *mut c_void,
+            event: *const c_char,
+            callback: *mut c_void,
+        ) -> e_ws_status;
+        pub(super) fn websocket_frame_create(opcode: e_ws_frame_opcode) -> *mut c_void;
+        pub(super) fn websocket_frame_push(
+            frame: *mut c_void,
+            data: *const c_uchar,
+            len: usize,
+        ) -> bool;
+        pub(super) fn websocket_frame_emit(ctx: *mut c_void, fd: c_int, frame: *mut c_void)
+        -> bool;
+        pub(super) fn websocket_frame_destroy(frame: *mut c_void);
+    }
+}
+
+/// Resolves `name` from `lib`, naming the symbol in the error if it is missing.
+///
+/// # Safety
+///
+/// `T` must match the native signature of `name`.
+#[cfg(not(feature = "static-link"))]
+unsafe fn symbol<T: Copy>(lib: &Library, name: &'static str) -> Result<T, WsError> {
+    unsafe { lib.get::<T>(name.as_bytes()) }
+        .map(|sym| *sym)
+        .map_err(|source| WsError::SymbolMissing { name, source })
+}
+
+impl Api {
+    /// The version the native library reports, if it exports one.
+    pub(crate) fn version(&self) -> Option<Version> {
+        let ptr = unsafe { (self.version?)() };
+        if ptr.is_null() {
+            return None;
+        }
+        Version::parse(unsafe { CStr::from_ptr(ptr) }.to_str().ok()?)
+    }
+}
+
+/// Loads the native library on first use and returns the shared symbol table.
+pub(crate) fn api() -> Result<&'static Api, WsError> {
+    static API: OnceLock<Api> = OnceLock::new();
+    if let Some(api) = API.get() {
+        return Ok(api);
+    }
+    let api = unsafe { Api::load()? };
+    Ok(API.get_or_init(|| api))
+}
+
+pub(crate) fn check(status: e_ws_status, err: impl FnOnce() -> WsError) -> Result<(), WsError> {
+    match WsStatus::from_raw(status) {
+        WsStatus::Ok => Ok(()),
+        WsStatus::Error => Err(err()),
+    }
+}
+
+/// A native frame handle, destroyed on drop.
+pub(crate) struct RawFrame {
+    api: &'static Api,
+    pub(crate) ptr: *mut c_void,
+    /// Copy of the payload, for frame tracing; the native frame cannot be
+    /// read back.
+    #[cfg(feature = "frame-trace")]
+    pub(crate) payload: Vec<u8>,
+}
+
+impl RawFrame {
+    pub(crate) fn new(api: &'static Api, opcode: Opcode) -> Result<Self, WsError> {
+        let ptr = unsafe { (api.frame_create)(opcode.to_raw()) };
+        if ptr.is_null() {
+            return Err(WsError::FrameCreate { opcode });
+        }
+        Ok(RawFrame {
+            api,
+            ptr,
+            #[cfg(feature = "frame-trace")]
+            payload: Vec::new(),
+        })
+    }
+
+    pub(crate) fn push(&mut self, data: &[u8]) -> Result<(), WsError> {
+        if unsafe { (self.api.frame_push)(self.ptr, data.as_ptr(), data.len()) } {
+            #[cfg(feature = "frame-trace")]
+            self.payload.extend_from_slice(data);
+            Ok(())
+        } else {
+            Err(WsError::FramePush { len: data.len() })
+        }
+    }
+}
+
+impl Drop for RawFrame {
+    fn drop(&mut self) {
+        unsafe { (self.api.frame_destroy)(self.ptr) };
+    }
+}
+
+/// Copyable view of a native context, usable from inside the callback shims.
+///
+/// It does not own the context; the owning `WebSocket` must outlive it.
+#[derive(Clone, Copy)]
+pub(crate) struct Context {
+    pub(crate) api: &'static Api,
+    pub(crate) ctx: *mut c_void,
+}
+
+impl Context {
+    pub(crate) fn frame(&self, opcode: Opcode, data: &[u8]) -> Result<RawFrame, WsError> {
+        let mut frame = RawFrame::new(self.api, opcode)?;
+        frame.push(data)?;
+        Ok(frame)
+    }
+
+    pub(crate) fn emit(&self, id: ConnectionId, frame: &RawFrame) -> Result<(), WsError> {
+        if unsafe { (self.api.frame_emit)(self.ctx, id.as_raw(), frame.ptr) } {
+            Ok(())
+        } else {
+            Err(WsError::Emit { id })
+        }
+    }
+}
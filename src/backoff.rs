@@ -0,0 +1,37 @@
+use std::thread;
+use std::time::Duration;
+
+/// First sleep after the initial yield.
+const MIN_SLEEP: Duration = Duration::from_micros(50);
+
+/// Adaptive sleep for idle event loop iterations.
+///
+/// The first idle iteration only yields; after that the sleep starts at
+/// [`MIN_SLEEP`] and doubles up to `cap`. Any activity resets it to zero.
+pub(crate) struct IdleBackoff {
+    cap: Duration,
+    current: Duration,
+}
+
+impl IdleBackoff {
+    pub(crate) fn new(cap: Duration) -> Self {
+        IdleBackoff {
+            cap,
+            current: Duration::ZERO,
+        }
+    }
+
+    pub(crate) fn reset(&mut self) {
+        self.current = Duration::ZERO;
+    }
+
+    pub(crate) fn wait(&mut self) {
+        if self.current.is_zero() {
+            thread::yield_now();
+            self.current = MIN_SLEEP.min(self.cap);
+        } else {
+            thread::sleep(self.current);
+            self.current = (self.current * 2).min(self.cap);
+        }
+    }
+}
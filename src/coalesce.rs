@@ -0,0 +1,60 @@
+use crate::Opcode;
+use std::time::{Duration, Instant};
+
+/// Data frames held back for one connection so consecutive ones of the same
+/// opcode go out as a single frame.
+pub(crate) struct Coalescer {
+    window: Duration,
+    pending: Option<Held>,
+}
+
+/// Payload of the frame being built, with when its first part was sent.
+pub(crate) struct Held {
+    pub(crate) opcode: Opcode,
+    pub(crate) data: Vec<u8>,
+    since: Instant,
+}
+
+impl Coalescer {
+    pub(crate) fn new(window: Duration) -> Self {
+        Coalescer {
+            window,
+            pending: None,
+        }
+    }
+
+    /// Appends `data` to the held frame if it has the same opcode. Otherwise
+    /// starts a new one with `data` and returns the frame it replaces, which
+    /// must be emitted first.
+    pub(crate) fn push(&mut self, opcode: Opcode, data: &[u8]) -> Option<Held> {
+        if let Some(held) = &mut self.pending
+            && held.opcode == opcode
+        {
+            held.data.extend_from_slice(data);
+            return None;
+        }
+        self.pending.replace(Held {
+            opcode,
+            data: data.to_vec(),
+            since: Instant::now(),
+        })
+    }
+
+    /// Takes the held frame.
+    pub(crate) fn take(&mut self) -> Option<Held> {
+        self.pending.take()
+    }
+
+    /// Takes the held frame if it has been held for the whole window.
+    pub(crate) fn take_expired(&mut self, now: Instant) -> Option<Held> {
+        if self
+            .pending
+            .as_ref()
+            .is_some_and(|held| now.duration_since(held.since) >= self.window)
+        {
+            self.pending.take()
+        } else {
+            None
+        }
+    }
+}
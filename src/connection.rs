@@ -0,0 +1,208 @@
+use crate::events::State;
+use crate::{ClosureStatus, Endpoint, Frame, Opcode, PeerAddr, WsError};
+use std::collections::HashMap;
+use std::ffi::c_int;
+use std::fmt;
+use std::time::SystemTime;
+
+/// Identifies a connection.
+///
+/// Wraps the socket fd the native library reports, so it cannot be confused
+/// with other integers. Ids are only unique among open connections; the
+/// native library may hand an id out again once its connection has closed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ConnectionId(c_int);
+
+impl ConnectionId {
+    /// Wraps a raw fd, e.g. one received by a callback registered with
+    /// [`WebSocket::on_event`](crate::WebSocket::on_event).
+    pub fn from_raw(fd: c_int) -> Self {
+        ConnectionId(fd)
+    }
+
+    pub fn as_raw(self) -> c_int {
+        self.0
+    }
+}
+
+impl fmt::Display for ConnectionId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Metadata about an open connection, recorded when it opened.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConnectionInfo {
+    pub id: ConnectionId,
+    /// Peer address as reported by the native library, if it could be parsed.
+    pub peer: Option<PeerAddr>,
+    pub connected_at: SystemTime,
+    /// Override of the settings' `message_limit` for this connection; see
+    /// [`WebSocket::set_message_limit`](crate::WebSocket::set_message_limit).
+    pub message_limit: Option<usize>,
+}
+
+/// Frames and payload bytes of one kind, counted by [`ConnectionStats`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Traffic {
+    pub frames: u64,
+    pub bytes: u64,
+}
+
+impl Traffic {
+    fn add(&mut self, other: Traffic) {
+        self.frames += other.frames;
+        self.bytes += other.bytes;
+    }
+}
+
+/// Traffic counters of an open connection, returned by
+/// [`WebSocket::connection_stats`](crate::WebSocket::connection_stats).
+///
+/// Bytes are payload bytes as the application sees them, before
+/// compression on the way out and after decompression on the way in. The
+/// native library does not report what went over the wire, so with
+/// `permessage-deflate` the compressed sizes, and with them the compression
+/// ratio, cannot be counted here; frame headers are not counted either.
+/// Received frames are counted as they arrive, including ones later dropped
+/// by the rate or message limits. Sent frames are counted once emitted,
+/// whether sent directly, queued through a [`Sender`](crate::Sender) or
+/// broadcast, along with the pongs answering pings.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ConnectionStats {
+    pub received: HashMap<Opcode, Traffic>,
+    pub sent: HashMap<Opcode, Traffic>,
+}
+
+impl ConnectionStats {
+    /// Everything received, over all opcodes.
+    pub fn total_received(&self) -> Traffic {
+        total(&self.received)
+    }
+
+    /// Everything sent, over all opcodes.
+    pub fn total_sent(&self) -> Traffic {
+        total(&self.sent)
+    }
+
+    pub(crate) fn count_received(&mut self, opcode: Opcode, len: usize) {
+        count(&mut self.received, opcode, len);
+    }
+
+    pub(crate) fn count_sent(&mut self, opcode: Opcode, len: usize) {
+        count(&mut self.sent, opcode, len);
+    }
+}
+
+fn total(by_opcode: &HashMap<Opcode, Traffic>) -> Traffic {
+    let mut sum = Traffic::default();
+    for traffic in by_opcode.values() {
+        sum.add(*traffic);
+    }
+    sum
+}
+
+fn count(by_opcode: &mut HashMap<Opcode, Traffic>, opcode: Opcode, len: usize) {
+    by_opcode.entry(opcode).or_default().add(Traffic {
+        frames: 1,
+        bytes: len as u64,
+    });
+}
+
+/// A connection handed to an event handler.
+///
+/// The handle borrows the context for the duration of the callback, so it
+/// cannot be stored and used after the handler returns; use
+/// [`WebSocket::sender`](crate::WebSocket::sender) with [`id`](Self::id) to
+/// send later or from another thread.
+#[derive(Clone, Copy)]
+pub struct Connection<'a> {
+    state: &'a State,
+    id: ConnectionId,
+}
+
+impl<'a> Connection<'a> {
+    pub(crate) fn new(state: &'a State, id: ConnectionId) -> Self {
+        Connection { state, id }
+    }
+
+    pub fn id(&self) -> ConnectionId {
+        self.id
+    }
+
+    /// Which side of the connection this context plays, from the
+    /// [`endpoint`](crate::SettingsBuilder::endpoint) it was set up with.
+    ///
+    /// Lets one handler serve client and server contexts in the same
+    /// process.
+    pub fn endpoint(&self) -> Endpoint {
+        self.state.endpoint.get()
+    }
+
+    pub fn is_client(&self) -> bool {
+        self.endpoint() == Endpoint::Client
+    }
+
+    pub fn is_server(&self) -> bool {
+        self.endpoint() == Endpoint::Server
+    }
+
+    /// Whether the frame or message being delivered was cut to the
+    /// `message_limit` under [`LimitAction::Truncate`](crate::LimitAction::Truncate).
+    ///
+    /// Only meaningful inside the frame and message handlers.
+    pub fn is_truncated(&self) -> bool {
+        self.state.truncated.get()
+    }
+
+    /// A stream of the frames received on this connection from now on,
+    /// ending when it closes. Replaces, and ends, a stream taken before.
+    ///
+    /// Take it in the [`on_open`](crate::WebSocket::on_open) handler to see
+    /// every frame.
+    #[cfg(feature = "stream")]
+    pub fn frames(&self) -> crate::FrameStream {
+        let (sink, stream) = crate::stream::channel();
+        self.state.streams.borrow_mut().insert(self.id, sink);
+        stream
+    }
+
+    /// Sends `text` as a single text frame.
+    pub fn send_text(&self, text: &str) -> Result<(), WsError> {
+        self.state.send(self.id, Opcode::Text, text.as_bytes())
+    }
+
+    /// Sends `data` as a single binary frame.
+    pub fn send_binary(&self, data: &[u8]) -> Result<(), WsError> {
+        self.state.send(self.id, Opcode::Binary, data)
+    }
+
+    /// Emits a prebuilt `frame`. The frame can be emitted again.
+    pub fn emit(&self, frame: &Frame) -> Result<(), WsError> {
+        self.state.send_batch(self.id, core::slice::from_ref(frame))
+    }
+
+    /// Starts the close handshake, as [`WebSocket::close`](crate::WebSocket::close).
+    pub fn close(&self, status: ClosureStatus, reason: &str) -> Result<(), WsError> {
+        self.state.close(self.id, status, reason)
+    }
+
+    /// Overrides the message limit for this connection, as
+    /// [`WebSocket::set_message_limit`](crate::WebSocket::set_message_limit).
+    pub fn set_message_limit(&self, bytes: usize) -> Result<(), WsError> {
+        self.state.set_message_limit(self.id, bytes)
+    }
+
+    /// Starts the close handshake without a status, as
+    /// [`WebSocket::close_bare`](crate::WebSocket::close_bare).
+    pub fn close_bare(&self) -> Result<(), WsError> {
+        self.state.close_bare(self.id)
+    }
+}
+
+impl fmt::Debug for Connection<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Connection").field("id", &self.id).finish()
+    }
+}
@@ -0,0 +1,126 @@
+use crate::events::panic_message;
+use crate::{Sender, WebSocket, WsError};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::thread::{self, JoinHandle};
+
+/// Why a [`WebSocket::spawn`]ed event loop ended, returned by
+/// [`ServerController::wait`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StopReason {
+    /// [`ServerController::stop`] was called.
+    Stopped,
+    /// Code running on the loop panicked outside a native callback, with
+    /// this message. Handler panics inside native callbacks are caught
+    /// earlier and do not end the loop; see
+    /// [`WsError::HandlerPanicked`].
+    Panicked(String),
+}
+
+/// Handle to a context running on a background thread, returned by
+/// [`WebSocket::spawn`].
+///
+/// Dropping the controller without calling [`stop`](Self::stop) leaves the
+/// thread running until its context stops on its own.
+pub struct ServerController {
+    stop: Arc<AtomicBool>,
+    sender: Sender,
+    handle: JoinHandle<Result<StopReason, WsError>>,
+}
+
+impl ServerController {
+    /// Asks the event loop to return. The context is then dropped, and
+    /// destroyed, on its own thread.
+    pub fn stop(&self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+
+    /// A [`Sender`] for queueing frames to the background context.
+    pub fn sender(&self) -> Sender {
+        self.sender.clone()
+    }
+
+    /// Whether the event loop has returned.
+    pub fn is_finished(&self) -> bool {
+        self.handle.is_finished()
+    }
+
+    /// Waits for the thread to finish. Call [`stop`](Self::stop) first
+    /// unless the context stops on its own.
+    ///
+    /// A panic that ended the loop is returned as the error, with its
+    /// message as a `String` payload; use [`wait`](Self::wait) to tell the
+    /// other reasons apart.
+    pub fn join(self) -> thread::Result<()> {
+        match self.handle.join()? {
+            Ok(StopReason::Panicked(message)) => Err(Box::new(message)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Waits for the thread to finish and returns why its loop ended:
+    /// [`StopReason::Stopped`] after [`stop`](Self::stop), or
+    /// [`StopReason::Panicked`]. A native event loop that stopped on its own
+    /// is returned as [`WsError::EventLoopStopped`].
+    pub fn wait(self) -> Result<StopReason, WsError> {
+        self.handle
+            .join()
+            .unwrap_or_else(|payload| Ok(StopReason::Panicked(panic_message(&*payload))))
+    }
+}
+
+impl WebSocket {
+    /// Runs a context on a dedicated thread and returns a controller for it.
+    ///
+    /// The context is created, passed to `configure` (register handlers,
+    /// `setup`, `bind` or `open`), driven with [`run`](Self::run)-style
+    /// backoff and finally destroyed, all on that thread, so a native
+    /// library that expects a context to stay on one thread is satisfied.
+    /// Returns once `configure` has finished, with its error if it failed.
+    ///
+    /// A handler that panics inside a native callback does not end the loop:
+    /// the panic is caught, reported to [`on_error`](Self::on_error) as
+    /// [`WsError::HandlerPanicked`] and the connection concerned is closed
+    /// with [`ClosureStatus::InternalError`](crate::ClosureStatus::InternalError).
+    /// Any other panic on the loop is caught as well, ends it, and is
+    /// returned by [`ServerController::wait`].
+    pub fn spawn(
+        configure: impl FnOnce(&WebSocket) -> Result<(), WsError> + Send + 'static,
+    ) -> Result<ServerController, WsError> {
+        let stop = Arc::new(AtomicBool::new(false));
+        let (ready_tx, ready_rx) = mpsc::channel();
+
+        let thread_stop = Arc::clone(&stop);
+        let handle = thread::spawn(move || {
+            // On failure `ready_tx` is dropped unsent and `spawn` takes the
+            // error from the joined thread.
+            let ws = WebSocket::new()?;
+            configure(&ws)?;
+            let _ = ready_tx.send(ws.sender());
+            let run = panic::catch_unwind(AssertUnwindSafe(|| {
+                ws.run_while(|| !thread_stop.load(Ordering::Relaxed));
+            }));
+            match run {
+                Err(payload) => Ok(StopReason::Panicked(panic_message(&*payload))),
+                Ok(()) if thread_stop.load(Ordering::Relaxed) => Ok(StopReason::Stopped),
+                Ok(()) => Err(WsError::EventLoopStopped),
+            }
+        });
+
+        match ready_rx.recv() {
+            Ok(sender) => Ok(ServerController {
+                stop,
+                sender,
+                handle,
+            }),
+            // Setup failed, or the thread panicked, before reporting back.
+            Err(_) => match handle.join() {
+                Err(panic) => panic::resume_unwind(panic),
+                Ok(Err(e)) => Err(e),
+                Ok(Ok(_)) => Err(WsError::ContextGone),
+            },
+        }
+    }
+}
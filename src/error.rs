@@ -0,0 +1,373 @@
+use crate::ConnectionId;
+use crate::Opcode;
+use std::ffi::NulError;
+use std::fmt;
+use std::path::PathBuf;
+use std::str::Utf8Error;
+
+/// Broad category of an error, passed to
+/// [`WebSocket::on_error_detailed`](crate::WebSocket::on_error_detailed).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ErrorKind {
+    /// Accepting or admitting a new connection failed.
+    Accept,
+    /// TLS setup or a TLS handshake failed.
+    Tls,
+    /// A peer broke the WebSocket protocol.
+    Protocol,
+    /// Reading from or writing to a socket failed.
+    Io,
+    /// Anything else, including native messages that match no category.
+    Other,
+}
+
+impl ErrorKind {
+    /// Categorizes a message from the native `error` callback.
+    ///
+    /// The native library only reports a message, so this looks for
+    /// keywords among its words and falls back to [`Other`](Self::Other).
+    pub fn classify(msg: &str) -> Self {
+        let msg = msg.to_ascii_lowercase();
+        let has = |keywords: &[&str]| {
+            msg.split(|c: char| !c.is_ascii_alphanumeric())
+                .any(|word| keywords.contains(&word))
+        };
+        if has(&["ssl", "tls", "certificate", "x509"]) {
+            ErrorKind::Tls
+        } else if has(&["accept", "listen"]) {
+            ErrorKind::Accept
+        } else if has(&["handshake", "upgrade", "protocol", "opcode", "mask", "utf"]) {
+            ErrorKind::Protocol
+        } else if has(&[
+            "recv", "send", "read", "write", "socket", "pipe", "reset", "eof",
+        ]) {
+            ErrorKind::Io
+        } else {
+            ErrorKind::Other
+        }
+    }
+}
+
+/// Errors returned by the safe wrapper.
+#[derive(Debug)]
+pub enum WsError {
+    /// The native library was not found in any of the searched locations.
+    LibraryNotFound {
+        name: &'static str,
+        tried: Vec<PathBuf>,
+    },
+    /// The native library was found, but a DLL it depends on could not be.
+    LibraryDependency {
+        path: PathBuf,
+        source: libloading::Error,
+    },
+    /// The native library was found but could not be loaded.
+    LibraryLoad {
+        path: PathBuf,
+        source: libloading::Error,
+    },
+    /// A required function is missing from the native library, usually
+    /// because its version does not match these bindings.
+    SymbolMissing {
+        name: &'static str,
+        source: libloading::Error,
+    },
+    /// The native library's major version differs from the one these
+    /// bindings were generated for.
+    VersionMismatch {
+        expected: crate::Version,
+        found: crate::Version,
+    },
+    /// `websocket_create` returned a null context.
+    Create,
+    /// `websocket_setup` rejected the settings.
+    Setup,
+    /// `websocket_bind` failed.
+    Bind { host: String, port: u16 },
+    /// The native library cannot serve `what`, such as a Unix domain socket.
+    Unsupported { what: &'static str },
+    /// `websocket_open` failed.
+    Open { host: String, port: u16 },
+    /// Resolving `host` took longer than the
+    /// [`connect_timeout`](crate::SettingsBuilder::connect_timeout).
+    Timeout {
+        host: String,
+        timeout: std::time::Duration,
+    },
+    /// A server did not complete the upgrade within the
+    /// [`handshake_timeout`](crate::SettingsBuilder::handshake_timeout).
+    HandshakeTimeout {
+        host: String,
+        port: u16,
+        timeout: std::time::Duration,
+    },
+    /// `websocket_on` rejected a callback registration.
+    Register { event: String },
+    /// `websocket_frame_create` returned a null frame.
+    FrameCreate { opcode: Opcode },
+    /// `websocket_frame_push` failed to append the payload.
+    FramePush { len: usize },
+    /// `websocket_frame_emit` failed to send the frame.
+    Emit { id: ConnectionId },
+    /// A control frame payload exceeded
+    /// [`MAX_CONTROL_PAYLOAD`](crate::MAX_CONTROL_PAYLOAD) bytes.
+    ControlFrameTooLarge { len: usize },
+    /// The close status is reserved and may not be sent on the wire.
+    InvalidCloseStatus { code: u16 },
+    /// A close frame was already sent on this connection.
+    ConnectionClosed { id: ConnectionId },
+    /// No connection with this id is open.
+    NotConnected { id: ConnectionId },
+    /// A connection was refused because `max` connections were already open.
+    TooManyConnections { id: ConnectionId, max: usize },
+    /// A connection sent more than `max` frames per second.
+    RateLimited { id: ConnectionId, max: u32 },
+    /// A continuation frame arrived with no message in progress.
+    UnexpectedContinuation { id: ConnectionId },
+    /// A text frame or message was not valid UTF-8 while
+    /// [`strict_utf8`](crate::SettingsBuilder::strict_utf8) was enabled.
+    InvalidUtf8 { id: ConnectionId, source: Utf8Error },
+    /// A frame with a reserved opcode arrived while
+    /// [`strict_protocol`](crate::SettingsBuilder::strict_protocol) was
+    /// enabled.
+    ReservedOpcode { id: ConnectionId, opcode: i32 },
+    /// A [`Sender`](crate::Sender) send was refused because the connection
+    /// already had `pending` bytes queued, near the high-water mark.
+    Backpressure { id: ConnectionId, pending: usize },
+    /// Reading or setting a socket option on a connection failed.
+    SocketOption {
+        id: ConnectionId,
+        option: &'static str,
+        source: std::io::Error,
+    },
+    /// Reading the payload for [`Frame::read_from`](crate::Frame::read_from)
+    /// failed.
+    StreamRead { source: std::io::Error },
+    /// No response to a [`RequestMap::request`](crate::RequestMap::request)
+    /// arrived within `timeout`.
+    RequestTimeout {
+        id: ConnectionId,
+        timeout: std::time::Duration,
+    },
+    /// A later [`RequestMap::request`](crate::RequestMap::request) to
+    /// connection `id` took over the correlation id of this one.
+    RequestReplaced { id: ConnectionId },
+    /// The [`WebSocket`](crate::WebSocket) behind a [`Sender`](crate::Sender)
+    /// has been dropped.
+    ContextGone,
+    /// A string passed to the native library contained an interior nul byte.
+    InvalidString(NulError),
+    /// A settings value was rejected before reaching the native library.
+    InvalidSetting { name: &'static str, reason: String },
+    /// A TLS certificate or key file could not be read.
+    TlsFile {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    /// [`ClientReconnect`](crate::ClientReconnect) stopped retrying.
+    ReconnectGaveUp { attempts: u32 },
+    /// The Ctrl+C handler could not be installed.
+    Signal(ctrlc::Error),
+    /// A handler panicked while the native library was calling into Rust.
+    /// The panic was caught, and the connection it concerned, if any, closed
+    /// with [`ClosureStatus::InternalError`](crate::ClosureStatus::InternalError).
+    HandlerPanicked {
+        id: Option<ConnectionId>,
+        message: String,
+    },
+    /// The native event loop of a [`WebSocket::spawn`](crate::WebSocket::spawn)ed
+    /// context stopped on its own rather than through
+    /// [`ServerController::stop`](crate::ServerController::stop).
+    EventLoopStopped,
+}
+
+impl WsError {
+    /// The category this error falls under.
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            WsError::TooManyConnections { .. } => ErrorKind::Accept,
+            WsError::TlsFile { .. } => ErrorKind::Tls,
+            WsError::RateLimited { .. }
+            | WsError::UnexpectedContinuation { .. }
+            | WsError::ReservedOpcode { .. }
+            | WsError::InvalidUtf8 { .. } => ErrorKind::Protocol,
+            WsError::Emit { .. }
+            | WsError::Timeout { .. }
+            | WsError::HandshakeTimeout { .. }
+            | WsError::SocketOption { .. }
+            | WsError::StreamRead { .. } => ErrorKind::Io,
+            _ => ErrorKind::Other,
+        }
+    }
+
+    /// The connection the error concerns, if it is about a single one.
+    pub fn connection(&self) -> Option<ConnectionId> {
+        match self {
+            WsError::Emit { id }
+            | WsError::ConnectionClosed { id }
+            | WsError::NotConnected { id }
+            | WsError::TooManyConnections { id, .. }
+            | WsError::UnexpectedContinuation { id }
+            | WsError::RateLimited { id, .. }
+            | WsError::RequestTimeout { id, .. }
+            | WsError::RequestReplaced { id }
+            | WsError::InvalidUtf8 { id, .. }
+            | WsError::ReservedOpcode { id, .. }
+            | WsError::Backpressure { id, .. }
+            | WsError::SocketOption { id, .. } => Some(*id),
+            WsError::HandlerPanicked { id, .. } => *id,
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for WsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WsError::LibraryNotFound { name, tried } => {
+                write!(f, "native library {name} not found; tried:")?;
+                for path in tried {
+                    write!(f, "\n  {}", path.display())?;
+                }
+                Ok(())
+            }
+            WsError::LibraryDependency { path, source } => write!(
+                f,
+                "failed to load {}: {source}; the file exists, so a DLL it depends on \
+                 (such as OpenSSL's libssl/libcrypto) is probably missing from its \
+                 directory and from PATH",
+                path.display()
+            ),
+            WsError::LibraryLoad { path, source } => {
+                write!(f, "failed to load {}: {source}", path.display())
+            }
+            WsError::VersionMismatch { expected, found } => write!(
+                f,
+                "native library version {found} is incompatible with bindings for {expected}"
+            ),
+            WsError::SymbolMissing { name, .. } => {
+                write!(
+                    f,
+                    "native library is missing symbol `{name}`; \
+                     its version probably does not match these bindings"
+                )
+            }
+            WsError::Create => f.write_str("websocket_create failed"),
+            WsError::Setup => f.write_str("websocket_setup rejected the settings"),
+            WsError::Bind { host, port } => write!(f, "failed to bind {host}:{port}"),
+            WsError::Unsupported { what } => {
+                write!(f, "not supported by the native library: {what}")
+            }
+            WsError::Open { host, port } => write!(f, "failed to connect to {host}:{port}"),
+            WsError::Timeout { host, timeout } => {
+                write!(f, "resolving {host} took longer than {timeout:?}")
+            }
+            WsError::HandshakeTimeout {
+                host,
+                port,
+                timeout,
+            } => write!(
+                f,
+                "{host}:{port} did not complete the upgrade within {timeout:?}"
+            ),
+            WsError::Register { event } => {
+                write!(f, "failed to register callback for `{event}`")
+            }
+            WsError::FrameCreate { opcode } => {
+                write!(f, "failed to create {opcode:?} frame")
+            }
+            WsError::FramePush { len } => write!(f, "failed to push {len} bytes into frame"),
+            WsError::Emit { id } => write!(f, "failed to emit frame on connection {id}"),
+            WsError::ControlFrameTooLarge { len } => write!(
+                f,
+                "control frame payload is {len} bytes, limit is {}",
+                crate::MAX_CONTROL_PAYLOAD
+            ),
+            WsError::InvalidCloseStatus { code } => {
+                write!(f, "close status {code} may not be sent in a close frame")
+            }
+            WsError::ConnectionClosed { id } => write!(f, "connection {id} is closing"),
+            WsError::NotConnected { id } => write!(f, "no open connection {id}"),
+            WsError::RateLimited { id, max } => {
+                write!(f, "connection {id} exceeded {max} frames per second")
+            }
+            WsError::RequestTimeout { id, timeout } => {
+                write!(f, "no response from connection {id} within {timeout:?}")
+            }
+            WsError::RequestReplaced { id } => {
+                write!(f, "request to connection {id} replaced by a later one")
+            }
+            WsError::StreamRead { source } => write!(f, "could not read frame payload: {source}"),
+            WsError::SocketOption { id, option, source } => {
+                write!(f, "socket option {option} on connection {id}: {source}")
+            }
+            WsError::ReservedOpcode { id, opcode } => {
+                write!(
+                    f,
+                    "connection {id} sent a frame with reserved opcode {opcode:#x}"
+                )
+            }
+            WsError::InvalidUtf8 { id, source } => {
+                write!(f, "invalid UTF-8 in text from connection {id}: {source}")
+            }
+            WsError::TooManyConnections { id, max } => {
+                write!(f, "refused connection {id}: {max} connections already open")
+            }
+            WsError::UnexpectedContinuation { id } => {
+                write!(
+                    f,
+                    "continuation frame on connection {id} without an initial frame"
+                )
+            }
+            WsError::Backpressure { id, pending } => write!(
+                f,
+                "send to connection {id} refused: {pending} bytes already queued"
+            ),
+            WsError::ContextGone => f.write_str("websocket context has been dropped"),
+            WsError::InvalidString(e) => write!(f, "invalid string: {e}"),
+            WsError::InvalidSetting { name, reason } => {
+                write!(f, "invalid setting `{name}`: {reason}")
+            }
+            WsError::TlsFile { path, source } => {
+                write!(f, "failed to read TLS file {}: {source}", path.display())
+            }
+            WsError::ReconnectGaveUp { attempts } => {
+                write!(f, "gave up reconnecting after {attempts} attempts")
+            }
+            WsError::Signal(e) => write!(f, "failed to install Ctrl+C handler: {e}"),
+            WsError::HandlerPanicked {
+                id: Some(id),
+                message,
+            } => {
+                write!(f, "handler panicked on connection {id}: {message}")
+            }
+            WsError::HandlerPanicked { id: None, message } => {
+                write!(f, "handler panicked: {message}")
+            }
+            WsError::EventLoopStopped => f.write_str("native event loop stopped on its own"),
+        }
+    }
+}
+
+impl std::error::Error for WsError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            WsError::LibraryDependency { source, .. } => Some(source),
+            WsError::LibraryLoad { source, .. } => Some(source),
+            WsError::SymbolMissing { source, .. } => Some(source),
+            WsError::InvalidString(e) => Some(e),
+            WsError::TlsFile { source, .. } => Some(source),
+            WsError::InvalidUtf8 { source, .. } => Some(source),
+            WsError::SocketOption { source, .. } => Some(source),
+            WsError::StreamRead { source } => Some(source),
+            WsError::Signal(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<NulError> for WsError {
+    fn from(e: NulError) -> Self {
+        WsError::InvalidString(e)
+    }
+}
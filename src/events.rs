@@ -0,0 +1,1148 @@
+//! Closure-based event handlers and the `extern "C"` shims that drive them.
+//!
+//! The native callbacks only receive the context pointer, so every live
+//! [`WebSocket`](crate::WebSocket) registers its handler state here keyed by
+//! that pointer. The shims look the state up and forward to the closures.
+
+use crate::api::{Context, RawFrame};
+use crate::coalesce::{Coalescer, Held};
+use crate::rate::TokenBucket;
+use crate::sys::*;
+use crate::{
+    ClosureStatus, Connection, ConnectionId, ConnectionInfo, ConnectionStats, Endpoint, ErrorKind,
+    Frame, LimitAction, MAX_CONTROL_PAYLOAD, Opcode, PeerAddr, PolledEvent, WsError,
+    check_control_payload,
+};
+use std::any::Any;
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::ffi::{CStr, c_char, c_int, c_uchar, c_void};
+use std::panic::{self, AssertUnwindSafe};
+use std::str::Utf8Error;
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+pub(crate) type OpenHandler = Box<dyn FnMut(Connection<'_>, Option<&PeerAddr>) + Send>;
+pub(crate) type CloseHandler = Box<dyn FnMut(ConnectionId, ClosureStatus, Option<String>) + Send>;
+pub(crate) type FrameHandler = Box<dyn FnMut(Connection<'_>, Opcode, &[u8]) + Send>;
+#[cfg(feature = "bytes")]
+pub(crate) type FrameBytesHandler = Box<dyn FnMut(Connection<'_>, Opcode, bytes::Bytes) + Send>;
+pub(crate) type MessageHandler = Box<dyn FnMut(Connection<'_>, Opcode, &[u8]) + Send>;
+pub(crate) type PongHandler = Box<dyn FnMut(ConnectionId, &[u8]) + Send>;
+pub(crate) type LimitHandler = Box<dyn FnMut(ConnectionId, usize) + Send>;
+pub(crate) type BackpressureHandler = Box<dyn FnMut(ConnectionId, usize) + Send>;
+pub(crate) type DrainHandler = Box<dyn FnMut(ConnectionId) + Send>;
+pub(crate) type ErrorHandler = Box<dyn FnMut(&str) + Send>;
+pub(crate) type DetailedErrorHandler = Box<dyn FnMut(Option<ConnectionId>, ErrorKind, &str) + Send>;
+
+/// Idle backoff cap used until settings are applied; matches the builder
+/// default.
+const IDLE_BACKOFF_CAP: Duration = Duration::from_millis(5);
+
+/// Close handshake timeout used until settings are applied; matches the
+/// builder default.
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Timed-out client opens remembered for closing a late upgrade; past
+/// this, the oldest is forgotten and its upgrade, should it complete, opens.
+const EXPIRED_HANDSHAKES_KEPT: usize = 64;
+
+/// A message being reassembled from its fragments.
+struct Partial {
+    opcode: Opcode,
+    data: Vec<u8>,
+    /// Cut to the message limit; later fragments are dropped.
+    truncated: bool,
+    /// Over the message limit under [`LimitAction::Ignore`]; later
+    /// fragments are dropped and nothing is delivered.
+    discarded: bool,
+}
+
+/// A client open waiting for its upgrade to complete.
+pub(crate) struct PendingOpen {
+    pub(crate) host: String,
+    pub(crate) port: u16,
+    pub(crate) started: Instant,
+}
+
+/// Per-context handler and connection storage.
+pub(crate) struct State {
+    pub(crate) context: Context,
+    pub(crate) open: RefCell<Option<OpenHandler>>,
+    pub(crate) close: RefCell<Option<CloseHandler>>,
+    pub(crate) frame: RefCell<Option<FrameHandler>>,
+    #[cfg(feature = "bytes")]
+    pub(crate) frame_bytes: RefCell<Option<FrameBytesHandler>>,
+    pub(crate) message: RefCell<Option<MessageHandler>>,
+    pub(crate) pong: RefCell<Option<PongHandler>>,
+    pub(crate) limit_exceeded: RefCell<Option<LimitHandler>>,
+    pub(crate) backpressure: RefCell<Option<BackpressureHandler>>,
+    pub(crate) drain: RefCell<Option<DrainHandler>>,
+    pub(crate) error: RefCell<Option<ErrorHandler>>,
+    pub(crate) error_detailed: RefCell<Option<DetailedErrorHandler>>,
+    /// The currently open connections.
+    pub(crate) connections: RefCell<HashMap<ConnectionId, ConnectionInfo>>,
+    /// Connections with coalescing enabled, with the data held back.
+    pub(crate) coalescing: RefCell<HashMap<ConnectionId, Coalescer>>,
+    /// Streams of the connections that have one; see `Connection::frames`.
+    #[cfg(feature = "stream")]
+    pub(crate) streams: RefCell<HashMap<ConnectionId, crate::stream::FrameSink>>,
+    /// Traffic counters of each open connection.
+    pub(crate) stats: RefCell<HashMap<ConnectionId, ConnectionStats>>,
+    /// When each open connection last received a data frame.
+    pub(crate) last_data: RefCell<HashMap<ConnectionId, Instant>>,
+    /// When each open connection last received any frame, or opened.
+    pub(crate) last_activity: RefCell<HashMap<ConnectionId, Instant>>,
+    /// Application data attached to open connections.
+    pub(crate) user_data: RefCell<HashMap<ConnectionId, Box<dyn Any>>>,
+    /// Fds we have sent a close frame on, with when; sending to them is
+    /// refused.
+    pub(crate) closing: RefCell<HashMap<ConnectionId, Instant>>,
+    /// Connections closed on open, for exceeding `max_connections` or while
+    /// draining, or already reported closed after a stalled close handshake.
+    /// Their remaining native events are ignored.
+    refused: RefCell<HashSet<ConnectionId>>,
+    /// Reasons from the close frames peers sent, until their connection
+    /// closes.
+    close_reasons: RefCell<HashMap<ConnectionId, String>>,
+    /// Status codes from the close frames peers sent, [`ClosureStatus::NoStatus`]
+    /// for an empty one, until their connection closes.
+    close_statuses: RefCell<HashMap<ConnectionId, ClosureStatus>>,
+    /// Messages being reassembled for the message handler, keyed by connection.
+    fragments: RefCell<HashMap<ConnectionId, Partial>>,
+    /// Status of the most recent close, if any connection has closed.
+    pub(crate) last_close: Cell<Option<ClosureStatus>>,
+    /// `message_limit` of the applied settings; unlimited until `setup`.
+    pub(crate) message_limit: Cell<usize>,
+    /// `limit_action` of the applied settings.
+    pub(crate) limit_action: Cell<LimitAction>,
+    /// Whether the frame or message being delivered was truncated.
+    pub(crate) truncated: Cell<bool>,
+    /// `idle_timeout` of the applied settings.
+    pub(crate) idle_timeout: Cell<Option<Duration>>,
+    /// `close_timeout` of the applied settings.
+    pub(crate) close_timeout: Cell<Duration>,
+    /// `idle_backoff_cap` of the applied settings.
+    pub(crate) idle_backoff_cap: Cell<Duration>,
+    /// `connect_timeout` of the applied settings.
+    pub(crate) connect_timeout: Cell<Option<Duration>>,
+    /// `handshake_timeout` of the applied settings.
+    pub(crate) handshake_timeout: Cell<Option<Duration>>,
+    /// Client opens whose upgrade has not completed, oldest first.
+    pub(crate) handshakes: RefCell<VecDeque<PendingOpen>>,
+    /// Client opens given up on by the handshake timeout, oldest first, so
+    /// an upgrade completing late can still be closed.
+    expired_handshakes: RefCell<VecDeque<PendingOpen>>,
+    /// `max_frames_per_sec` of the applied settings.
+    pub(crate) max_frames_per_sec: Cell<Option<u32>>,
+    /// Inbound frame budget of each open connection, while rate limited.
+    buckets: RefCell<HashMap<ConnectionId, TokenBucket>>,
+    /// `max_connections` of the applied settings.
+    pub(crate) max_connections: Cell<Option<usize>>,
+    /// Set by `WebSocket::drain`; new connections are refused.
+    pub(crate) draining: Cell<bool>,
+    /// `endpoint` of the applied settings.
+    pub(crate) endpoint: Cell<Endpoint>,
+    /// `strict_utf8` of the applied settings.
+    pub(crate) strict_utf8: Cell<bool>,
+    /// `strict_protocol` of the applied settings.
+    pub(crate) strict_protocol: Cell<bool>,
+    /// `frame_trace` of the applied settings.
+    #[cfg(feature = "frame-trace")]
+    pub(crate) frame_trace: Cell<bool>,
+    /// Events queued for `WebSocket::operate_poll`; `None` until it is first
+    /// called, so contexts that do not poll copy nothing.
+    pub(crate) polled: RefCell<Option<VecDeque<PolledEvent>>>,
+    /// Set whenever a native callback fires; cleared by `operate_with_timeout`.
+    pub(crate) active: Cell<bool>,
+    /// Connections opened plus frames received, for `WebSocket::operate_n`.
+    pub(crate) serviced: Cell<usize>,
+}
+
+impl State {
+    pub(crate) fn new(context: Context) -> Self {
+        State {
+            context,
+            open: RefCell::default(),
+            close: RefCell::default(),
+            frame: RefCell::default(),
+            #[cfg(feature = "bytes")]
+            frame_bytes: RefCell::default(),
+            message: RefCell::default(),
+            pong: RefCell::default(),
+            limit_exceeded: RefCell::default(),
+            backpressure: RefCell::default(),
+            drain: RefCell::default(),
+            error: RefCell::default(),
+            error_detailed: RefCell::default(),
+            connections: RefCell::default(),
+            user_data: RefCell::default(),
+            coalescing: RefCell::default(),
+            #[cfg(feature = "stream")]
+            streams: RefCell::default(),
+            stats: RefCell::default(),
+            last_data: RefCell::default(),
+            last_activity: RefCell::default(),
+            closing: RefCell::default(),
+            refused: RefCell::default(),
+            close_reasons: RefCell::default(),
+            close_statuses: RefCell::default(),
+            fragments: RefCell::default(),
+            last_close: Cell::default(),
+            message_limit: Cell::new(usize::MAX),
+            limit_action: Cell::default(),
+            truncated: Cell::default(),
+            idle_timeout: Cell::default(),
+            close_timeout: Cell::new(CLOSE_TIMEOUT),
+            idle_backoff_cap: Cell::new(IDLE_BACKOFF_CAP),
+            endpoint: Cell::new(Endpoint::Client),
+            strict_utf8: Cell::default(),
+            strict_protocol: Cell::new(true),
+            #[cfg(feature = "frame-trace")]
+            frame_trace: Cell::default(),
+            max_connections: Cell::default(),
+            connect_timeout: Cell::default(),
+            handshake_timeout: Cell::default(),
+            handshakes: RefCell::default(),
+            expired_handshakes: RefCell::default(),
+            max_frames_per_sec: Cell::default(),
+            buckets: RefCell::default(),
+            draining: Cell::default(),
+            polled: RefCell::default(),
+            active: Cell::default(),
+            serviced: Cell::default(),
+        }
+    }
+
+    /// Drops every registered handler.
+    pub(crate) fn clear_handlers(&self) {
+        self.open.take();
+        self.close.take();
+        self.frame.take();
+        #[cfg(feature = "bytes")]
+        self.frame_bytes.take();
+        self.message.take();
+        self.pong.take();
+        self.limit_exceeded.take();
+        self.backpressure.take();
+        self.drain.take();
+        self.error.take();
+        self.error_detailed.take();
+    }
+
+    /// Abandons every open connection and forgets everything kept about
+    /// connections, keeping handlers and settings.
+    ///
+    /// `refused` is kept, so native events still due for connections
+    /// dropped here are ignored.
+    pub(crate) fn reset(&self) {
+        let open: Vec<ConnectionId> = self.connections.borrow().keys().copied().collect();
+        for id in open {
+            self.abandon(id);
+        }
+        self.closing.borrow_mut().clear();
+        self.close_reasons.borrow_mut().clear();
+        self.close_statuses.borrow_mut().clear();
+        self.fragments.borrow_mut().clear();
+        self.handshakes.borrow_mut().clear();
+        self.expired_handshakes.borrow_mut().clear();
+        self.last_close.set(None);
+        self.draining.set(false);
+        if let Some(polled) = self.polled.borrow_mut().as_mut() {
+            polled.clear();
+        }
+    }
+
+    /// Sends a frame to `id` unless a close frame was already sent on it.
+    pub(crate) fn send(
+        &self,
+        id: ConnectionId,
+        opcode: Opcode,
+        data: &[u8],
+    ) -> Result<(), WsError> {
+        if self.closing.borrow().contains_key(&id) {
+            return Err(WsError::ConnectionClosed { id });
+        }
+        if matches!(opcode, Opcode::Text | Opcode::Binary)
+            && let Some(coalescer) = self.coalescing.borrow_mut().get_mut(&id)
+        {
+            return match coalescer.push(opcode, data) {
+                Some(held) => self.emit_held(id, held),
+                None => Ok(()),
+            };
+        }
+        self.emit(id, opcode, data.len(), &self.context.frame(opcode, data)?)
+    }
+
+    /// Emits `frames` to `id` back to back.
+    ///
+    /// Every frame is checked before the first is emitted, so an oversized
+    /// control frame sends nothing. Emitting stops at the first failure.
+    pub(crate) fn send_batch(&self, id: ConnectionId, frames: &[Frame]) -> Result<(), WsError> {
+        if self.closing.borrow().contains_key(&id) {
+            return Err(WsError::ConnectionClosed { id });
+        }
+        for frame in frames.iter().filter(|f| f.opcode().is_control()) {
+            if frame.len() > MAX_CONTROL_PAYLOAD {
+                return Err(WsError::ControlFrameTooLarge { len: frame.len() });
+            }
+        }
+        frames
+            .iter()
+            .try_for_each(|frame| self.emit(id, frame.opcode(), frame.len(), frame.raw()))
+    }
+
+    /// Emits `frame`, an `opcode` frame with a `len` byte payload, to `id`,
+    /// after any data held back for coalescing.
+    pub(crate) fn emit(
+        &self,
+        id: ConnectionId,
+        opcode: Opcode,
+        len: usize,
+        frame: &RawFrame,
+    ) -> Result<(), WsError> {
+        self.flush(id)?;
+        self.emit_counted(id, opcode, len, frame)
+    }
+
+    /// Emits the frame held back for coalescing on `id`, if any.
+    pub(crate) fn flush(&self, id: ConnectionId) -> Result<(), WsError> {
+        let held = self
+            .coalescing
+            .borrow_mut()
+            .get_mut(&id)
+            .and_then(Coalescer::take);
+        match held {
+            Some(held) => self.emit_held(id, held),
+            None => Ok(()),
+        }
+    }
+
+    /// Emits every coalesced frame held back for its whole window.
+    pub(crate) fn flush_coalesced(&self) {
+        let now = Instant::now();
+        let expired: Vec<(ConnectionId, Held)> = self
+            .coalescing
+            .borrow_mut()
+            .iter_mut()
+            .filter_map(|(&id, coalescer)| Some((id, coalescer.take_expired(now)?)))
+            .collect();
+        for (id, held) in expired {
+            if let Err(e) = self.emit_held(id, held) {
+                self.report(&e);
+            }
+        }
+    }
+
+    fn emit_held(&self, id: ConnectionId, held: Held) -> Result<(), WsError> {
+        let frame = self.context.frame(held.opcode, &held.data)?;
+        self.emit_counted(id, held.opcode, held.data.len(), &frame)
+    }
+
+    /// Emits `frame` to `id` and counts it in the connection's stats.
+    fn emit_counted(
+        &self,
+        id: ConnectionId,
+        opcode: Opcode,
+        len: usize,
+        frame: &RawFrame,
+    ) -> Result<(), WsError> {
+        self.context.emit(id, frame)?;
+        #[cfg(feature = "frame-trace")]
+        if self.frame_trace.get() {
+            trace_frame(id, "sent", opcode, &frame.payload);
+        }
+        if let Some(stats) = self.stats.borrow_mut().get_mut(&id) {
+            stats.count_sent(opcode, len);
+        }
+        Ok(())
+    }
+
+    /// Sends a close frame with `status` and `reason` and marks `id` as
+    /// closing.
+    pub(crate) fn close(
+        &self,
+        id: ConnectionId,
+        status: ClosureStatus,
+        reason: &str,
+    ) -> Result<(), WsError> {
+        if self.closing.borrow().contains_key(&id) {
+            return Err(WsError::ConnectionClosed { id });
+        }
+        if !status.is_sendable() {
+            return Err(WsError::InvalidCloseStatus {
+                code: status.code(),
+            });
+        }
+        let mut payload = Vec::with_capacity(2 + reason.len());
+        payload.extend_from_slice(&status.code().to_be_bytes());
+        payload.extend_from_slice(reason.as_bytes());
+        check_control_payload(&payload)?;
+        self.close_with(id, &payload)
+    }
+
+    /// Sends a close frame with no payload, hence no status, and marks `id`
+    /// as closing.
+    pub(crate) fn close_bare(&self, id: ConnectionId) -> Result<(), WsError> {
+        if self.closing.borrow().contains_key(&id) {
+            return Err(WsError::ConnectionClosed { id });
+        }
+        self.close_with(id, &[])
+    }
+
+    fn close_with(&self, id: ConnectionId, payload: &[u8]) -> Result<(), WsError> {
+        let frame = self.context.frame(Opcode::Close, payload)?;
+        self.emit(id, Opcode::Close, payload.len(), &frame)?;
+        self.closing.borrow_mut().insert(id, Instant::now());
+        Ok(())
+    }
+
+    /// Buffers a data frame for the message handler.
+    ///
+    /// The native frame callback does not carry the FIN bit, so a message is
+    /// delivered once the next message starts on the same connection, the connection
+    /// closes, or the current event loop iteration ends.
+    ///
+    /// `truncated` says the frame was already cut to the limit, and reported.
+    fn buffer_message(&self, id: ConnectionId, opcode: Opcode, payload: &[u8], truncated: bool) {
+        // Data arriving after our close frame is discarded.
+        if self.message.borrow().is_none() || self.closing.borrow().contains_key(&id) {
+            return;
+        }
+        match opcode {
+            Opcode::Text | Opcode::Binary => {
+                self.flush_message(id);
+                self.fragments.borrow_mut().insert(
+                    id,
+                    Partial {
+                        opcode,
+                        data: payload.to_vec(),
+                        truncated,
+                        discarded: false,
+                    },
+                );
+            }
+            Opcode::Continuation => {
+                let mut fragments = self.fragments.borrow_mut();
+                let Some(partial) = fragments.get_mut(&id) else {
+                    drop(fragments);
+                    self.report(&WsError::UnexpectedContinuation { id });
+                    if let Err(e) = self.close(id, ClosureStatus::ProtocolError, "") {
+                        self.report(&e);
+                    }
+                    return;
+                };
+                if partial.truncated || partial.discarded {
+                    return;
+                }
+                let size = partial.data.len() + payload.len();
+                let limit = self.message_limit(id);
+                if !truncated && size <= limit {
+                    partial.data.extend_from_slice(payload);
+                    return;
+                }
+                match self.limit_action.get() {
+                    LimitAction::Close => {
+                        fragments.remove(&id);
+                    }
+                    LimitAction::Ignore => {
+                        partial.discarded = true;
+                        partial.data = Vec::new();
+                    }
+                    LimitAction::Truncate => {
+                        partial.data.extend_from_slice(payload);
+                        let len = truncated_len(partial.opcode, &partial.data, limit);
+                        partial.data.truncate(len);
+                        partial.truncated = true;
+                    }
+                }
+                drop(fragments);
+                if !truncated {
+                    self.limit_exceeded(id, size);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Drops the message `opcode` starts or continues on `id`, including its
+    /// later fragments.
+    fn discard_message(&self, id: ConnectionId, opcode: Opcode) {
+        if self.message.borrow().is_none() {
+            return;
+        }
+        match opcode {
+            Opcode::Text | Opcode::Binary => {
+                self.flush_message(id);
+                self.fragments.borrow_mut().insert(
+                    id,
+                    Partial {
+                        opcode,
+                        data: Vec::new(),
+                        truncated: false,
+                        discarded: true,
+                    },
+                );
+            }
+            Opcode::Continuation => {
+                if let Some(partial) = self.fragments.borrow_mut().get_mut(&id) {
+                    partial.discarded = true;
+                    partial.data = Vec::new();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Delivers the message buffered for `id`, if any.
+    fn flush_message(&self, id: ConnectionId) {
+        let message = self.fragments.borrow_mut().remove(&id);
+        if let Some(partial) = message
+            && !partial.discarded
+        {
+            if partial.opcode == Opcode::Text
+                && self.strict_utf8.get()
+                && let Err(e) = str::from_utf8(&partial.data)
+            {
+                self.invalid_utf8(id, e);
+                return;
+            }
+            self.truncated.set(partial.truncated);
+            dispatch(&self.message, |h| {
+                h(Connection::new(self, id), partial.opcode, &partial.data)
+            });
+            self.truncated.set(false);
+        }
+    }
+
+    /// Delivers every buffered message.
+    pub(crate) fn flush_messages(&self) {
+        let ids: Vec<ConnectionId> = self.fragments.borrow().keys().copied().collect();
+        for id in ids {
+            self.guard(Some(id), || self.flush_message(id));
+        }
+    }
+
+    /// The message limit for `id`: its override, or the settings' limit.
+    fn message_limit(&self, id: ConnectionId) -> usize {
+        self.connections
+            .borrow()
+            .get(&id)
+            .and_then(|info| info.message_limit)
+            .unwrap_or(self.message_limit.get())
+    }
+
+    /// Overrides the message limit for `id`.
+    pub(crate) fn set_message_limit(&self, id: ConnectionId, bytes: usize) -> Result<(), WsError> {
+        match self.connections.borrow_mut().get_mut(&id) {
+            Some(info) => {
+                info.message_limit = Some(bytes);
+                Ok(())
+            }
+            None => Err(WsError::NotConnected { id }),
+        }
+    }
+
+    /// Reports, and stops waiting for, every client open whose upgrade has
+    /// taken longer than the handshake timeout.
+    pub(crate) fn expire_handshakes(&self) {
+        let Some(timeout) = self.handshake_timeout.get() else {
+            return;
+        };
+        let now = Instant::now();
+        let expired: VecDeque<PendingOpen> = {
+            let mut handshakes = self.handshakes.borrow_mut();
+            let (expired, pending) = handshakes
+                .drain(..)
+                .partition(|open| now.duration_since(open.started) >= timeout);
+            *handshakes = pending;
+            expired
+        };
+        for open in expired {
+            self.report(&WsError::HandshakeTimeout {
+                host: open.host.clone(),
+                port: open.port,
+                timeout,
+            });
+            let mut kept = self.expired_handshakes.borrow_mut();
+            if kept.len() == EXPIRED_HANDSHAKES_KEPT {
+                kept.pop_front();
+            }
+            kept.push_back(open);
+        }
+    }
+
+    /// Stops waiting for the client open that `peer` answers: the one to the
+    /// same host and port, else the oldest to the same port. The peer is
+    /// reported as an address, so an open made by name usually matches on
+    /// its port alone. An upgrade matching no pending open leaves them all
+    /// to time out.
+    ///
+    /// Returns whether `peer` answers an open the handshake timeout already
+    /// gave up on, matched by host and port, which is then to be closed.
+    fn settle_handshake(&self, peer: Option<&PeerAddr>) -> bool {
+        let Some(peer) = peer else {
+            return false;
+        };
+        let answers = |open: &PendingOpen| {
+            Some(open.port) == peer.port && open.host.eq_ignore_ascii_case(&peer.host)
+        };
+        let mut handshakes = self.handshakes.borrow_mut();
+        if let Some(index) = handshakes.iter().position(answers) {
+            handshakes.remove(index);
+            return false;
+        }
+        let mut expired = self.expired_handshakes.borrow_mut();
+        if let Some(index) = expired.iter().position(answers) {
+            expired.remove(index);
+            return true;
+        }
+        if let Some(index) = handshakes
+            .iter()
+            .position(|open| Some(open.port) == peer.port)
+        {
+            handshakes.remove(index);
+        }
+        false
+    }
+
+    /// Closes every connection that has been idle longer than the idle
+    /// timeout.
+    pub(crate) fn close_idle(&self) {
+        let Some(timeout) = self.idle_timeout.get() else {
+            return;
+        };
+        let now = Instant::now();
+        let idle: Vec<ConnectionId> = self
+            .last_data
+            .borrow()
+            .iter()
+            .filter(|&(_, &last)| now.duration_since(last) > timeout)
+            .map(|(&id, _)| id)
+            .collect();
+        for id in idle {
+            self.last_data.borrow_mut().remove(&id);
+            if self.closing.borrow().contains_key(&id) {
+                continue;
+            }
+            ws_log!(info, fd = id.as_raw(); "closing idle connection");
+            if let Err(e) = self.close(id, ClosureStatus::PolicyViolation, "idle timeout") {
+                self.report(&e);
+            }
+        }
+    }
+
+    /// Gives up on close handshakes the peer has not completed within
+    /// `close_timeout`: the close handler sees [`ClosureStatus::Abnormal`]
+    /// straight away; see `abandon`.
+    pub(crate) fn close_stalled(&self) {
+        let timeout = self.close_timeout.get();
+        let now = Instant::now();
+        let stalled: Vec<ConnectionId> = self
+            .closing
+            .borrow()
+            .iter()
+            .filter(|&(_, &since)| now.duration_since(since) > timeout)
+            .map(|(&id, _)| id)
+            .collect();
+        for id in stalled {
+            ws_log!(warn, fd = id.as_raw(); "close handshake timed out");
+            self.abandon(id);
+        }
+    }
+
+    /// Gives up on `id`, reporting it closed with
+    /// [`ClosureStatus::Abnormal`] straight away.
+    ///
+    /// The C API has no call to drop a single connection, and its socket
+    /// belongs to the native library, so all this can do is send a close
+    /// frame if none was sent yet. The native library keeps the connection
+    /// until the peer answers or goes away, and whatever it reports about
+    /// `id` afterwards is ignored.
+    pub(crate) fn abandon(&self, id: ConnectionId) {
+        if !self.closing.borrow().contains_key(&id) {
+            let _ = self.close(id, ClosureStatus::GoingAway, "");
+        }
+        if self.refused.borrow_mut().insert(id) {
+            self.closed(id, ClosureStatus::Abnormal);
+        } else {
+            self.closing.borrow_mut().remove(&id);
+        }
+    }
+
+    /// Delivers the close of `id` and forgets everything kept about it.
+    fn closed(&self, id: ConnectionId, status: ClosureStatus) {
+        self.flush_message(id);
+        // The peer's close frame, if it sent one, decides the status
+        // (RFC 6455, section 7.1.5).
+        let status = self
+            .close_statuses
+            .borrow_mut()
+            .remove(&id)
+            .unwrap_or(status);
+        self.last_close.set(Some(status));
+        ws_log!(info, fd = id.as_raw(), status:? = status; "connection closed");
+        let reason = self.close_reasons.borrow_mut().remove(&id);
+        dispatch(&self.close, |h| h(id, status, reason.clone()));
+        self.queue(|| PolledEvent::Close { id, status, reason });
+        self.connections.borrow_mut().remove(&id);
+        self.user_data.borrow_mut().remove(&id);
+        self.last_data.borrow_mut().remove(&id);
+        self.last_activity.borrow_mut().remove(&id);
+        self.stats.borrow_mut().remove(&id);
+        self.coalescing.borrow_mut().remove(&id);
+        // Dropping the sink ends the stream.
+        #[cfg(feature = "stream")]
+        self.streams.borrow_mut().remove(&id);
+        self.buckets.borrow_mut().remove(&id);
+        self.closing.borrow_mut().remove(&id);
+    }
+
+    /// Reports invalid UTF-8 from `id` and closes it with
+    /// [`ClosureStatus::InvalidPayload`].
+    fn invalid_utf8(&self, id: ConnectionId, source: Utf8Error) {
+        self.fragments.borrow_mut().remove(&id);
+        self.report(&WsError::InvalidUtf8 { id, source });
+        if self.closing.borrow().contains_key(&id) {
+            return;
+        }
+        if let Err(e) = self.close(id, ClosureStatus::InvalidPayload, "invalid UTF-8") {
+            self.report(&e);
+        }
+    }
+
+    /// Reports a frame with a reserved opcode from `id` and closes it with
+    /// [`ClosureStatus::ProtocolError`].
+    fn reserved_opcode(&self, id: ConnectionId, opcode: i32) {
+        self.report(&WsError::ReservedOpcode { id, opcode });
+        if self.closing.borrow().contains_key(&id) {
+            return;
+        }
+        if let Err(e) = self.close(id, ClosureStatus::ProtocolError, "reserved opcode") {
+            self.report(&e);
+        }
+    }
+
+    /// Reports a message of `size` bytes over the limit, then, under
+    /// [`LimitAction::Close`], closes `id` with
+    /// [`ClosureStatus::MessageTooBig`].
+    fn limit_exceeded(&self, id: ConnectionId, size: usize) {
+        ws_log!(warn, fd = id.as_raw(), size; "message limit exceeded");
+        dispatch(&self.limit_exceeded, |h| h(id, size));
+        if self.limit_action.get() != LimitAction::Close || self.closing.borrow().contains_key(&id)
+        {
+            return;
+        }
+        if let Err(e) = self.close(id, ClosureStatus::MessageTooBig, "message too big") {
+            self.report(&e);
+        }
+    }
+
+    /// Takes a frame from `id`'s budget, reporting and closing it with
+    /// [`ClosureStatus::PolicyViolation`] when the budget is spent. Returns
+    /// whether the frame may be processed.
+    fn admit_frame(&self, id: ConnectionId) -> bool {
+        let Some(max) = self.max_frames_per_sec.get() else {
+            return true;
+        };
+        let now = Instant::now();
+        let admitted = self
+            .buckets
+            .borrow_mut()
+            .entry(id)
+            .or_insert_with(|| TokenBucket::new(max, now))
+            .try_take(now);
+        if admitted || self.closing.borrow().contains_key(&id) {
+            return admitted;
+        }
+        self.report(&WsError::RateLimited { id, max });
+        if let Err(e) = self.close(id, ClosureStatus::PolicyViolation, "rate limit exceeded") {
+            self.report(&e);
+        }
+        false
+    }
+
+    /// Closes a connection as it opens, hiding it from the open, close and
+    /// frame handlers.
+    fn refuse(&self, id: ConnectionId, status: ClosureStatus, reason: &str) {
+        self.refused.borrow_mut().insert(id);
+        if let Err(e) = self.close(id, status, reason) {
+            self.report(&e);
+        }
+    }
+
+    /// Runs `f`, the body of a native callback, catching a panic from a
+    /// handler so it cannot unwind into the native library.
+    ///
+    /// A panic is reported as [`WsError::HandlerPanicked`] and closes `id`,
+    /// if given, with [`ClosureStatus::InternalError`]. Returns `None` if `f`
+    /// panicked.
+    fn guard<R>(&self, id: Option<ConnectionId>, f: impl FnOnce() -> R) -> Option<R> {
+        let payload = match panic::catch_unwind(AssertUnwindSafe(f)) {
+            Ok(r) => return Some(r),
+            Err(payload) => payload,
+        };
+        // An error or close handler panicking in turn is dropped.
+        let _ = panic::catch_unwind(AssertUnwindSafe(|| {
+            self.report(&WsError::HandlerPanicked {
+                id,
+                message: panic_message(&*payload),
+            });
+            if let Some(id) = id
+                && self.connections.borrow().contains_key(&id)
+                && !self.closing.borrow().contains_key(&id)
+                && let Err(e) = self.close(id, ClosureStatus::InternalError, "")
+            {
+                self.report(&e);
+            }
+        }));
+        None
+    }
+
+    /// Reports an error raised on the Rust side to the error handlers.
+    pub(crate) fn report(&self, e: &WsError) {
+        self.report_detailed(e.connection(), e.kind(), &e.to_string());
+    }
+
+    /// Reports a message from the native library to the error handlers.
+    pub(crate) fn report_error(&self, msg: &str) {
+        self.report_detailed(None, ErrorKind::classify(msg), msg);
+    }
+
+    fn report_detailed(&self, id: Option<ConnectionId>, kind: ErrorKind, msg: &str) {
+        ws_log!(error, fd:? = id.map(ConnectionId::as_raw), kind:? = kind; "{msg}");
+        dispatch(&self.error, |h| h(msg));
+        dispatch(&self.error_detailed, |h| h(id, kind, msg));
+        self.queue(|| PolledEvent::Error {
+            id,
+            kind,
+            message: msg.to_owned(),
+        });
+    }
+
+    /// Queues the event built by `event` if the context is being polled.
+    fn queue(&self, event: impl FnOnce() -> PolledEvent) {
+        if let Some(queue) = self.polled.borrow_mut().as_mut() {
+            queue.push_back(event());
+        }
+    }
+}
+
+/// Maps a native context address to the address of its boxed [`State`].
+static CONTEXTS: LazyLock<Mutex<HashMap<usize, usize>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+pub(crate) fn register(ctx: *mut c_void, state: &State) {
+    CONTEXTS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .insert(ctx as usize, state as *const State as usize);
+}
+
+pub(crate) fn unregister(ctx: *mut c_void) {
+    CONTEXTS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .remove(&(ctx as usize));
+}
+
+/// Looks up the state for `ctx` and records that a callback fired.
+///
+/// # Safety
+///
+/// Must only be called from a native callback, while the owning
+/// `WebSocket` (and therefore its boxed state) is still alive.
+unsafe fn state<'a>(ctx: *mut c_void) -> Option<&'a State> {
+    let addr = *CONTEXTS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .get(&(ctx as usize))?;
+    let state = unsafe { &*(addr as *const State) };
+    state.active.set(true);
+    Some(state)
+}
+
+/// Calls the handler in `slot`, if any.
+///
+/// The closure is taken out for the duration of the call so a handler that
+/// re-enters the library cannot trigger a double borrow. If the slot was
+/// replaced in the meantime the new handler is kept. A panicking handler is
+/// put back before the panic resumes.
+pub(crate) fn dispatch<H>(slot: &RefCell<Option<H>>, call: impl FnOnce(&mut H)) {
+    let Some(mut handler) = slot.borrow_mut().take() else {
+        return;
+    };
+    let result = panic::catch_unwind(AssertUnwindSafe(|| call(&mut handler)));
+    {
+        let mut slot = slot.borrow_mut();
+        if slot.is_none() {
+            *slot = Some(handler);
+        }
+    }
+    if let Err(payload) = result {
+        panic::resume_unwind(payload);
+    }
+}
+
+/// The message a panic was raised with.
+pub(crate) fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(msg) = payload.downcast_ref::<&str>() {
+        (*msg).to_owned()
+    } else if let Some(msg) = payload.downcast_ref::<String>() {
+        msg.clone()
+    } else {
+        "<non-string panic payload>".to_owned()
+    }
+}
+
+/// Logs a frame's payload as hex.
+///
+/// Only what the native API exposes is logged: FIN, RSV bits, the masking
+/// key and the wire length are handled inside the native library, and with
+/// `permessage-deflate` the payload is the uncompressed one.
+#[cfg(feature = "frame-trace")]
+fn trace_frame(id: ConnectionId, direction: &str, opcode: Opcode, payload: &[u8]) {
+    use std::fmt::Write;
+
+    let mut hex = String::with_capacity(payload.len() * 3);
+    for byte in payload {
+        let _ = write!(hex, "{byte:02x} ");
+    }
+    ws_log!(trace, fd = id.as_raw(), direction, opcode:? = opcode, len = payload.len(); "frame {direction}: {}", hex.trim_end());
+}
+
+unsafe fn c_str<'a>(ptr: *const c_char) -> Option<&'a str> {
+    if ptr.is_null() {
+        None
+    } else {
+        unsafe { CStr::from_ptr(ptr) }.to_str().ok()
+    }
+}
+
+pub(crate) unsafe extern "C" fn on_open(ctx: *mut c_void, fd: c_int, addr: *const c_char) {
+    let Some(state) = (unsafe { state(ctx) }) else {
+        return;
+    };
+    let id = ConnectionId::from_raw(fd);
+    state.guard(Some(id), || {
+        state.serviced.set(state.serviced.get().wrapping_add(1));
+        // An abandoned connection's socket may be released without a close
+        // event, so an open on its fd starts afresh.
+        state.refused.borrow_mut().remove(&id);
+        state.closing.borrow_mut().remove(&id);
+        let addr = unsafe { c_str(addr) }.and_then(PeerAddr::parse);
+        if state.endpoint.get() == Endpoint::Client && state.settle_handshake(addr.as_ref()) {
+            ws_log!(info, fd = id.as_raw(); "closing upgrade completed after the handshake timeout");
+            state.refuse(id, ClosureStatus::GoingAway, "handshake timed out");
+            return;
+        }
+        if state.draining.get() {
+            ws_log!(info, fd = id.as_raw(); "connection refused while draining");
+            state.refuse(id, ClosureStatus::GoingAway, "server is draining");
+            return;
+        }
+        if let Some(max) = state.max_connections.get()
+            && state.connections.borrow().len() >= max
+        {
+            state.report(&WsError::TooManyConnections { id, max });
+            state.refuse(id, ClosureStatus::PolicyViolation, "too many connections");
+            return;
+        }
+        state.connections.borrow_mut().insert(
+            id,
+            ConnectionInfo {
+                id,
+                peer: addr.clone(),
+                connected_at: SystemTime::now(),
+                message_limit: None,
+            },
+        );
+        let now = Instant::now();
+        state.last_data.borrow_mut().insert(id, now);
+        state.last_activity.borrow_mut().insert(id, now);
+        state
+            .stats
+            .borrow_mut()
+            .insert(id, ConnectionStats::default());
+        ws_log!(info, fd = id.as_raw(), peer:? = addr; "connection opened");
+        dispatch(&state.open, |h| {
+            h(Connection::new(state, id), addr.as_ref())
+        });
+        state.queue(|| PolledEvent::Open { id, peer: addr });
+    });
+}
+
+pub(crate) unsafe extern "C" fn on_close(ctx: *mut c_void, fd: c_int, status: e_ws_closure_status) {
+    let Some(state) = (unsafe { state(ctx) }) else {
+        return;
+    };
+    let id = ConnectionId::from_raw(fd);
+    state.guard(None, || {
+        if state.refused.borrow_mut().remove(&id) {
+            state.closing.borrow_mut().remove(&id);
+            return;
+        }
+        state.closed(id, ClosureStatus::from_raw(status));
+    });
+}
+
+pub(crate) unsafe extern "C" fn on_frame(
+    ctx: *mut c_void,
+    fd: c_int,
+    opcode: e_ws_frame_opcode,
+    data: *const c_uchar,
+    len: usize,
+) {
+    let Some(state) = (unsafe { state(ctx) }) else {
+        return;
+    };
+    let id = ConnectionId::from_raw(fd);
+    state.guard(Some(id), || {
+        if state.refused.borrow().contains(&id) {
+            return;
+        }
+        let payload = if data.is_null() || len == 0 {
+            &[][..]
+        } else {
+            unsafe { core::slice::from_raw_parts(data, len) }
+        };
+        let opcode = Opcode::from_raw(opcode);
+        ws_log!(trace, fd = id.as_raw(), opcode:? = opcode, len; "frame received");
+        #[cfg(feature = "frame-trace")]
+        if state.frame_trace.get() {
+            trace_frame(id, "received", opcode, payload);
+        }
+        state.serviced.set(state.serviced.get().wrapping_add(1));
+        if let Some(stats) = state.stats.borrow_mut().get_mut(&id) {
+            stats.count_received(opcode, len);
+        }
+        if let Some(last) = state.last_activity.borrow_mut().get_mut(&id) {
+            *last = Instant::now();
+        }
+        if !state.admit_frame(id) {
+            return;
+        }
+        if let Opcode::Unknown(raw) = opcode
+            && state.strict_protocol.get()
+        {
+            state.reserved_opcode(id, raw);
+            return;
+        }
+        let limit = state.message_limit(id);
+        let truncated = len > limit;
+        let payload = if truncated {
+            state.limit_exceeded(id, len);
+            match state.limit_action.get() {
+                LimitAction::Close => return,
+                LimitAction::Ignore => {
+                    state.discard_message(id, opcode);
+                    return;
+                }
+                LimitAction::Truncate => &payload[..truncated_len(opcode, payload, limit)],
+            }
+        } else {
+            payload
+        };
+        // A frame cut off mid-character may still be completed by a continuation.
+        if opcode == Opcode::Text
+            && state.strict_utf8.get()
+            && let Err(e) = str::from_utf8(payload)
+            && e.error_len().is_some()
+        {
+            state.invalid_utf8(id, e);
+            return;
+        }
+        if !opcode.is_control()
+            && let Some(last) = state.last_data.borrow_mut().get_mut(&id)
+        {
+            *last = Instant::now();
+        }
+        match opcode {
+            Opcode::Ping => {
+                let pong = state.context.frame(Opcode::Pong, payload);
+                if let Err(e) = pong.and_then(|f| state.emit(id, Opcode::Pong, payload.len(), &f)) {
+                    state.report(&e);
+                }
+            }
+            Opcode::Pong => dispatch(&state.pong, |h| h(id, payload)),
+            // A status code may be followed by a UTF-8 reason. Only the
+            // first close frame counts.
+            Opcode::Close => {
+                let status = match payload {
+                    [] => Some(ClosureStatus::NoStatus),
+                    [hi, lo, ..] => Some(ClosureStatus::from_code(u16::from_be_bytes([*hi, *lo]))),
+                    // A lone byte is malformed; the native status stands.
+                    [_] => None,
+                };
+                if let Some(status) = status {
+                    state
+                        .close_statuses
+                        .borrow_mut()
+                        .entry(id)
+                        .or_insert(status);
+                }
+                if let Some(reason) = payload.get(2..)
+                    && !reason.is_empty()
+                    && let Ok(reason) = str::from_utf8(reason)
+                {
+                    state
+                        .close_reasons
+                        .borrow_mut()
+                        .insert(id, reason.to_owned());
+                }
+            }
+            _ => {}
+        }
+        state.truncated.set(truncated);
+        dispatch(&state.frame, |h| {
+            h(Connection::new(state, id), opcode, payload)
+        });
+        #[cfg(feature = "bytes")]
+        dispatch(&state.frame_bytes, |h| {
+            h(
+                Connection::new(state, id),
+                opcode,
+                bytes::Bytes::copy_from_slice(payload),
+            )
+        });
+        state.truncated.set(false);
+        #[cfg(feature = "stream")]
+        {
+            let mut streams = state.streams.borrow_mut();
+            if let Some(sink) = streams.get(&id)
+                && !sink.push(opcode, payload)
+            {
+                streams.remove(&id);
+            }
+        }
+        state.queue(|| PolledEvent::Frame {
+            id,
+            opcode,
+            data: payload.to_vec(),
+        });
+        state.buffer_message(id, opcode, payload, truncated);
+    });
+}
+
+/// Length of `data` cut to at most `limit` bytes. Text is cut back to the
+/// last complete character, so a message that was valid UTF-8 stays valid.
+fn truncated_len(opcode: Opcode, data: &[u8], limit: usize) -> usize {
+    let end = data.len().min(limit);
+    if opcode == Opcode::Text
+        && let Err(e) = str::from_utf8(&data[..end])
+        && e.error_len().is_none()
+    {
+        return e.valid_up_to();
+    }
+    end
+}
+
+pub(crate) unsafe extern "C" fn on_error(ctx: *mut c_void, msg: *const c_char) {
+    let Some(state) = (unsafe { state(ctx) }) else {
+        return;
+    };
+    state.guard(None, || {
+        let msg = unsafe { c_str(msg) }.unwrap_or("<unknown error>");
+        state.report_error(msg);
+    });
+}
@@ -0,0 +1,79 @@
+use std::fmt;
+
+/// The Cargo features this build of the crate was compiled with, returned by
+/// [`features`].
+///
+/// TLS and `permessage-deflate` are not among them: the wrapper always
+/// exposes [`Mode::Secured`](crate::Mode::Secured) and
+/// [`permessage_deflate`](crate::SettingsBuilder::permessage_deflate), and
+/// whether they work depends on how the native library was built (OpenSSL
+/// and zlib), which only shows as a `setup` or handshake failure. Likewise
+/// the crate has no runtime integration beyond the `stream` feature's
+/// [`futures_core::Stream`](https://docs.rs/futures-core).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FeatureSet {
+    /// `client`: the bundled binary runs as a client. The library itself
+    /// supports both roles in every build.
+    pub client: bool,
+    /// `server`: the bundled binary runs as a server.
+    pub server: bool,
+    /// `log`: events and errors go through the `log` crate.
+    pub log: bool,
+    /// `bytes`: `WebSocket::on_frame_bytes`.
+    pub bytes: bool,
+    /// `stream`: per-connection frame streams.
+    pub stream: bool,
+    /// `frame-trace`: hex logging of frame payloads.
+    pub frame_trace: bool,
+    /// `static-link`: the native library is linked in rather than loaded at
+    /// runtime.
+    pub static_link: bool,
+}
+
+impl FeatureSet {
+    /// Cargo names of the enabled features.
+    pub fn enabled(&self) -> impl Iterator<Item = &'static str> {
+        [
+            (self.client, "client"),
+            (self.server, "server"),
+            (self.log, "log"),
+            (self.bytes, "bytes"),
+            (self.stream, "stream"),
+            (self.frame_trace, "frame-trace"),
+            (self.static_link, "static-link"),
+        ]
+        .into_iter()
+        .filter_map(|(on, name)| on.then_some(name))
+    }
+}
+
+impl fmt::Display for FeatureSet {
+    /// The enabled features, comma-separated, or `none`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut names = self.enabled();
+        match names.next() {
+            Some(first) => {
+                f.write_str(first)?;
+                for name in names {
+                    write!(f, ", {name}")?;
+                }
+                Ok(())
+            }
+            None => f.write_str("none"),
+        }
+    }
+}
+
+/// The Cargo features this build was compiled with, for diagnostics such as
+/// logging them at startup.
+pub const fn features() -> FeatureSet {
+    FeatureSet {
+        client: cfg!(feature = "client"),
+        server: cfg!(feature = "server"),
+        log: cfg!(feature = "log"),
+        bytes: cfg!(feature = "bytes"),
+        stream: cfg!(feature = "stream"),
+        frame_trace: cfg!(feature = "frame-trace"),
+        static_link: cfg!(feature = "static-link"),
+    }
+}
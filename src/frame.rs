@@ -0,0 +1,121 @@
+use crate::api::{RawFrame, api};
+use crate::{Opcode, WsError};
+use std::fmt;
+use std::io::{self, Read};
+
+/// An owned native frame, built independently of any connection.
+///
+/// Payload is appended with [`push`](Self::push); the finished frame can be
+/// emitted any number of times with
+/// [`Connection::emit`](crate::Connection::emit),
+/// [`WebSocket::emit`](crate::WebSocket::emit) or as part of a
+/// [`send_batch`](crate::WebSocket::send_batch). The native frame is
+/// destroyed on drop, whether or not it was ever emitted.
+///
+/// There is no frame pool: the C API can only append to a frame, not clear
+/// it, so a frame cannot be recycled for a different payload. To avoid
+/// create/destroy churn when the same payload goes out repeatedly, build the
+/// frame once and emit it as often as needed, as
+/// [`broadcast_text`](crate::WebSocket::broadcast_text) does.
+///
+/// Compression cannot be chosen per frame either; see
+/// [`SettingsBuilder::permessage_deflate`](crate::SettingsBuilder::permessage_deflate).
+pub struct Frame {
+    raw: RawFrame,
+    opcode: Opcode,
+    len: usize,
+}
+
+// SAFETY: a native frame is a standalone buffer that is not tied to a
+// context, and `Frame` only hands out access to it through `&mut self` or
+// by emitting it from the thread that owns the context.
+unsafe impl Send for Frame {}
+
+impl Frame {
+    /// Creates an empty frame with `opcode`, loading the native library if
+    /// it has not been loaded yet.
+    pub fn with_opcode(opcode: Opcode) -> Result<Self, WsError> {
+        Ok(Frame {
+            raw: RawFrame::new(api()?, opcode)?,
+            opcode,
+            len: 0,
+        })
+    }
+
+    /// A text frame holding `text`.
+    pub fn text(text: &str) -> Result<Self, WsError> {
+        let mut frame = Frame::with_opcode(Opcode::Text)?;
+        frame.push(text.as_bytes())?;
+        Ok(frame)
+    }
+
+    /// A binary frame holding `data`.
+    pub fn binary(data: &[u8]) -> Result<Self, WsError> {
+        let mut frame = Frame::with_opcode(Opcode::Binary)?;
+        frame.push(data)?;
+        Ok(frame)
+    }
+
+    /// A frame with `opcode` holding everything `reader` yields, read
+    /// `chunk_size` bytes at a time.
+    ///
+    /// Each chunk is pushed into the native frame as soon as it is read, so
+    /// the Rust side never holds more than one chunk; the native frame still
+    /// grows to the full payload. Fails with [`WsError::StreamRead`] if
+    /// reading fails.
+    ///
+    /// A payload cannot be streamed as a fragmented message instead: the
+    /// native frame API has no FIN flag, so nothing marks the fragment that
+    /// ends the message, and a peer following RFC 6455 would never see it
+    /// complete. Payloads larger than the peer's message limit have to be
+    /// split into messages by the application.
+    pub fn read_from(
+        opcode: Opcode,
+        mut reader: impl Read,
+        chunk_size: usize,
+    ) -> Result<Self, WsError> {
+        let mut frame = Frame::with_opcode(opcode)?;
+        let mut chunk = vec![0; chunk_size.max(1)];
+        loop {
+            match reader.read(&mut chunk) {
+                Ok(0) => return Ok(frame),
+                Ok(n) => frame.push(&chunk[..n])?,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(source) => return Err(WsError::StreamRead { source }),
+            }
+        }
+    }
+
+    /// Appends `data` to the payload.
+    pub fn push(&mut self, data: &[u8]) -> Result<(), WsError> {
+        self.raw.push(data)?;
+        self.len += data.len();
+        Ok(())
+    }
+
+    pub fn opcode(&self) -> Opcode {
+        self.opcode
+    }
+
+    /// Payload length in bytes.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub(crate) fn raw(&self) -> &RawFrame {
+        &self.raw
+    }
+}
+
+impl fmt::Debug for Frame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Frame")
+            .field("opcode", &self.opcode)
+            .field("len", &self.len)
+            .finish()
+    }
+}
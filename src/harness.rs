@@ -0,0 +1,340 @@
+//! In-memory stand-in for the native library, for testing handlers without
+//! sockets.
+//!
+//! [`TestHarness`] runs a [`WebSocket`] against a symbol table of Rust
+//! functions instead of the native library: no library is loaded, no port
+//! is bound, and every event happens only when the test asks for it.
+
+use crate::api::Api;
+use crate::sys::*;
+use crate::{ClosureStatus, ConnectionId, Event, Opcode, WebSocket};
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet};
+use std::ffi::{CStr, CString, c_char, c_int, c_uchar, c_void};
+use std::mem;
+
+/// Any value but the error status reads as success.
+const OK: e_ws_status = if e_ws_status_status_error == 0 { 1 } else { 0 };
+
+/// The events the C API defines. Like a stock native build, the loopback
+/// refuses a callback for any other.
+const EVENTS: [&CStr; 4] = [c"open", c"close", c"frame", c"error"];
+
+/// Fds handed out by the loopback start here, well clear of real
+/// descriptors, so socket options set on a simulated connection, as with
+/// `WebSocket::set_nodelay`, fail instead of reaching an unrelated socket.
+const FIRST_FD: c_int = 1 << 20;
+
+/// The loopback's symbol table.
+static LOOPBACK: Api = Api {
+    create,
+    destroy,
+    setup,
+    operate,
+    bind,
+    open,
+    on,
+    frame_create,
+    frame_push,
+    frame_emit,
+    frame_destroy,
+    version: None,
+    #[cfg(not(feature = "static-link"))]
+    _lib: None,
+};
+
+/// A loopback context: the registered callbacks, every open and frame
+/// emitted, and the fds emits fail on.
+#[derive(Default)]
+struct Loopback {
+    callbacks: RefCell<HashMap<CString, *mut c_void>>,
+    opens: RefCell<Vec<(String, u16)>>,
+    sent: RefCell<Vec<(Opcode, Vec<u8>)>>,
+    broken: RefCell<HashSet<c_int>>,
+}
+
+/// A loopback frame.
+struct RawFrame {
+    opcode: Opcode,
+    data: Vec<u8>,
+}
+
+unsafe extern "C" fn create() -> *mut c_void {
+    Box::into_raw(Box::<Loopback>::default()).cast()
+}
+
+unsafe extern "C" fn destroy(ctx: *mut c_void) {
+    drop(unsafe { Box::from_raw(ctx.cast::<Loopback>()) });
+}
+
+/// Reads every string the settings point to, as the native library copies
+/// them, so a pointer that does not outlive the call shows up under Miri or
+/// a sanitizer.
+unsafe extern "C" fn setup(_: *mut c_void, settings: *const ws_settings_t) -> e_ws_status {
+    let settings = unsafe { &*settings };
+    for string in [
+        settings.host,
+        settings.allowed_origin,
+        settings.ssl_ca_cert,
+        settings.ssl_own_cert,
+        settings.ssl_private_key,
+    ] {
+        if !string.is_null() {
+            drop(unsafe { CStr::from_ptr(string) }.to_owned());
+        }
+    }
+    OK
+}
+
+unsafe extern "C" fn operate(_: *mut c_void) -> bool {
+    true
+}
+
+unsafe extern "C" fn bind(
+    _: *mut c_void,
+    _: *const c_char,
+    _: *const c_char,
+    out_port: *mut c_int,
+) -> e_ws_status {
+    if !out_port.is_null() {
+        unsafe { *out_port = 0 };
+    }
+    OK
+}
+
+unsafe extern "C" fn open(
+    ctx: *mut c_void,
+    host: *const c_char,
+    port: *const c_char,
+    out_port: *mut c_int,
+) -> e_ws_status {
+    let loopback = unsafe { &*ctx.cast::<Loopback>() };
+    let (host, port) = unsafe { (CStr::from_ptr(host), CStr::from_ptr(port)) };
+    loopback.opens.borrow_mut().push((
+        host.to_string_lossy().into_owned(),
+        port.to_str()
+            .ok()
+            .and_then(|port| port.parse().ok())
+            .unwrap_or(0),
+    ));
+    unsafe { bind(ctx, host.as_ptr(), port.as_ptr(), out_port) }
+}
+
+unsafe extern "C" fn on(
+    ctx: *mut c_void,
+    event: *const c_char,
+    callback: *mut c_void,
+) -> e_ws_status {
+    let loopback = unsafe { &*ctx.cast::<Loopback>() };
+    let event = unsafe { CStr::from_ptr(event) };
+    if !EVENTS.contains(&event) {
+        return e_ws_status_status_error;
+    }
+    loopback
+        .callbacks
+        .borrow_mut()
+        .insert(event.to_owned(), callback);
+    OK
+}
+
+unsafe extern "C" fn frame_create(opcode: e_ws_frame_opcode) -> *mut c_void {
+    Box::into_raw(Box::new(RawFrame {
+        opcode: Opcode::from_raw(opcode),
+        data: Vec::new(),
+    }))
+    .cast()
+}
+
+unsafe extern "C" fn frame_push(frame: *mut c_void, data: *const c_uchar, len: usize) -> bool {
+    let frame = unsafe { &mut *frame.cast::<RawFrame>() };
+    if len > 0 {
+        frame
+            .data
+            .extend_from_slice(unsafe { core::slice::from_raw_parts(data, len) });
+    }
+    true
+}
+
+unsafe extern "C" fn frame_emit(ctx: *mut c_void, fd: c_int, frame: *mut c_void) -> bool {
+    let loopback = unsafe { &*ctx.cast::<Loopback>() };
+    if loopback.broken.borrow().contains(&fd) {
+        return false;
+    }
+    let frame = unsafe { &*frame.cast::<RawFrame>() };
+    loopback
+        .sent
+        .borrow_mut()
+        .push((frame.opcode, frame.data.clone()));
+    true
+}
+
+unsafe extern "C" fn frame_destroy(frame: *mut c_void) {
+    drop(unsafe { Box::from_raw(frame.cast::<RawFrame>()) });
+}
+
+/// A [`WebSocket`] wired to an in-memory loopback instead of the native
+/// library, with a single simulated connection. Needs the `test` feature.
+///
+/// Register handlers and call `setup` through [`websocket`](Self::websocket),
+/// [`connect`](Self::connect), then [`feed_frame`](Self::feed_frame) what
+/// the peer would send and check [`sent_frames`](Self::sent_frames) for the
+/// replies:
+///
+/// ```
+/// use ws::{Opcode, TestHarness};
+///
+/// let harness = TestHarness::new();
+/// harness.websocket().on_frame(|conn, opcode, data| {
+///     conn.send_binary(data).unwrap();
+/// });
+/// harness.connect();
+/// harness.feed_frame(Opcode::Binary, b"echo");
+/// assert_eq!(harness.sent_frames(), [(Opcode::Binary, b"echo".to_vec())]);
+/// ```
+///
+/// Everything the wrapper does itself still runs: pings are answered,
+/// limits and UTF-8 checks applied, close frames tracked. What the native
+/// library would do on the wire does not: close handshakes never complete
+/// on their own (use [`disconnect`](Self::disconnect)), and timeouts only
+/// pass in real time.
+pub struct TestHarness {
+    ws: WebSocket,
+    id: Cell<Option<ConnectionId>>,
+    next_fd: Cell<c_int>,
+}
+
+impl TestHarness {
+    pub fn new() -> Self {
+        TestHarness {
+            ws: WebSocket::with_api(&LOOPBACK).expect("the loopback accepts the native events"),
+            id: Cell::new(None),
+            next_fd: Cell::new(FIRST_FD),
+        }
+    }
+
+    /// The context under test.
+    pub fn websocket(&self) -> &WebSocket {
+        &self.ws
+    }
+
+    /// Opens the simulated connection, as the native library does once a
+    /// handshake completes, and returns its id. A connection opened before
+    /// is replaced, without a close event.
+    pub fn connect(&self) -> ConnectionId {
+        let fd = self.next_fd.get();
+        self.next_fd.set(fd + 1);
+        self.open_fd(fd)
+    }
+
+    /// Opens a new connection on the fd of the current one, as the native
+    /// library may once it has released the old socket without a close
+    /// event, and returns its id.
+    ///
+    /// # Panics
+    ///
+    /// If [`connect`](Self::connect) has not been called.
+    pub fn reconnect(&self) -> ConnectionId {
+        self.open_fd(self.connected().as_raw())
+    }
+
+    fn open_fd(&self, fd: c_int) -> ConnectionId {
+        let id = ConnectionId::from_raw(fd);
+        self.id.set(Some(id));
+        if let Some(callback) = self.callback(Event::Open) {
+            let open: unsafe extern "C" fn(*mut c_void, c_int, *const c_char) =
+                unsafe { mem::transmute(callback) };
+            unsafe { open(self.ws.raw.ctx, fd, c"127.0.0.1:0".as_ptr()) };
+        }
+        id
+    }
+
+    /// Delivers a frame as if the peer had sent it on the connection.
+    ///
+    /// The frame handler sees it straight away. Messages reach the message
+    /// handler at the end of the next [`operate`](WebSocket::operate) call,
+    /// as with the native library.
+    ///
+    /// # Panics
+    ///
+    /// If [`connect`](Self::connect) has not been called.
+    pub fn feed_frame(&self, opcode: Opcode, bytes: &[u8]) {
+        let fd = self.connected().as_raw();
+        if let Some(callback) = self.callback(Event::Frame) {
+            let frame: unsafe extern "C" fn(
+                *mut c_void,
+                c_int,
+                e_ws_frame_opcode,
+                *const c_uchar,
+                usize,
+            ) = unsafe { mem::transmute(callback) };
+            unsafe {
+                frame(
+                    self.ws.raw.ctx,
+                    fd,
+                    opcode.to_raw(),
+                    bytes.as_ptr(),
+                    bytes.len(),
+                )
+            };
+        }
+    }
+
+    /// Closes the connection with `status`, as the native library reports
+    /// once a close handshake completes or the peer goes away.
+    ///
+    /// # Panics
+    ///
+    /// If [`connect`](Self::connect) has not been called.
+    pub fn disconnect(&self, status: ClosureStatus) {
+        let fd = self.connected().as_raw();
+        self.id.set(None);
+        if let Some(callback) = self.callback(Event::Close) {
+            let close: unsafe extern "C" fn(*mut c_void, c_int, e_ws_closure_status) =
+                unsafe { mem::transmute(callback) };
+            unsafe { close(self.ws.raw.ctx, fd, status.to_raw()) };
+        }
+    }
+
+    /// Makes every later emit to `id` fail, as for a peer whose socket died
+    /// before the native library noticed. No close event is delivered.
+    pub fn break_connection(&self, id: ConnectionId) {
+        self.loopback().broken.borrow_mut().insert(id.as_raw());
+    }
+
+    /// The hosts and ports the native library was asked to connect to so
+    /// far, oldest first.
+    pub fn opens(&self) -> Vec<(String, u16)> {
+        self.loopback().opens.borrow().clone()
+    }
+
+    /// The frames emitted so far, oldest first.
+    pub fn sent_frames(&self) -> Vec<(Opcode, Vec<u8>)> {
+        self.loopback().sent.borrow().clone()
+    }
+
+    fn connected(&self) -> ConnectionId {
+        self.id
+            .get()
+            .expect("TestHarness::connect must be called first")
+    }
+
+    fn loopback(&self) -> &Loopback {
+        unsafe { &*self.ws.raw.ctx.cast::<Loopback>() }
+    }
+
+    /// The callback registered for `event`, copied out so the loopback is
+    /// not borrowed while it runs.
+    fn callback(&self, event: Event) -> Option<*mut c_void> {
+        self.loopback()
+            .callbacks
+            .borrow()
+            .get(event.name())
+            .copied()
+    }
+}
+
+impl Default for TestHarness {
+    fn default() -> Self {
+        Self::new()
+    }
+}
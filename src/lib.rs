@@ -0,0 +1,1168 @@
+//! Safe Rust wrapper around the native C WebSocket library.
+//!
+//! The native library is loaded at runtime through `libloading`; the raw
+//! bindings generated by `bindgen` live in [`sys`].
+
+use std::any::Any;
+use std::cell::RefMut;
+use std::ffi::{CStr, CString, c_char, c_int, c_void};
+use std::sync::{Arc, Mutex, mpsc};
+use std::thread;
+use std::time::{Duration, Instant};
+
+pub mod sys {
+    #![allow(
+        non_upper_case_globals,
+        non_camel_case_types,
+        non_snake_case,
+        dead_code
+    )]
+
+    include!(concat!(env!("OUT_DIR"), "/bindings.rs"));
+}
+
+/// Forwards to the `log` crate macro `$level` when the `log` feature is
+/// enabled, and compiles to nothing otherwise.
+macro_rules! ws_log {
+    ($level:ident, $($arg:tt)+) => {
+        #[cfg(feature = "log")]
+        log::$level!(target: "ws", $($arg)+);
+    };
+}
+
+use api::{Api, Context, api, check};
+use backoff::IdleBackoff;
+use sys::*;
+
+mod api;
+mod backoff;
+mod coalesce;
+mod connection;
+mod controller;
+mod error;
+mod events;
+mod features;
+mod frame;
+#[cfg(feature = "test")]
+mod harness;
+mod library;
+mod peer;
+mod poll;
+mod rate;
+mod reactor;
+mod reconnect;
+mod request;
+mod resolve;
+mod sender;
+mod settings;
+mod shutdown;
+mod socket;
+#[cfg(feature = "stream")]
+mod stream;
+mod types;
+mod version;
+
+pub use connection::{Connection, ConnectionId, ConnectionInfo, ConnectionStats, Traffic};
+pub use controller::{ServerController, StopReason};
+pub use error::{ErrorKind, WsError};
+pub use features::{FeatureSet, features};
+pub use frame::Frame;
+#[cfg(feature = "test")]
+pub use harness::TestHarness;
+pub use library::{BUNDLED_LIB_DIR, LIB_PATH_ENV, find_library};
+pub use peer::PeerAddr;
+pub use poll::PolledEvent;
+pub use reactor::Reactor;
+pub use reconnect::ClientReconnect;
+pub use request::{RequestMap, Response};
+pub use sender::Sender;
+pub use settings::{Endpoint, LimitAction, Mode, Settings, SettingsBuilder, WINDOW_BITS_RANGE};
+#[cfg(feature = "stream")]
+pub use stream::FrameStream;
+pub use types::{ClosureStatus, Event, Opcode, OperateOutcome, WsStatus};
+pub use version::{BINDINGS_VERSION, Version};
+
+/// An owned native WebSocket context.
+///
+/// The context is destroyed when the value is dropped.
+///
+/// The native callbacks are installed once, by [`new`](Self::new), and
+/// forward to closures stored on the Rust side. Registering an `on_*`
+/// handler only swaps that closure, so it can be done at any time: before
+/// or after [`setup`](Self::setup) and between [`operate`](Self::operate)
+/// calls. The new handler sees every event from the next one on.
+pub struct WebSocket {
+    raw: Context,
+    state: Box<events::State>,
+    outbox_tx: mpsc::Sender<sender::Outgoing>,
+    outbox_rx: mpsc::Receiver<sender::Outgoing>,
+    pending: Arc<Mutex<sender::Pending>>,
+}
+
+impl WebSocket {
+    /// Loads the native library (once per process) and creates a new context.
+    ///
+    /// Fails with [`WsError::VersionMismatch`] if the library reports a
+    /// major version other than the one these bindings were generated for;
+    /// see [`native_version`](Self::native_version).
+    pub fn new() -> Result<Self, WsError> {
+        let api = api()?;
+        if let (Some(found), Some(expected)) = (api.version(), Version::bindings())
+            && !found.is_compatible_with(expected)
+        {
+            return Err(WsError::VersionMismatch { expected, found });
+        }
+        Self::with_api(api)
+    }
+
+    /// Creates a context through `api`, the native library or a stand-in.
+    fn with_api(api: &'static Api) -> Result<Self, WsError> {
+        let ctx = unsafe { (api.create)() };
+        if ctx.is_null() {
+            return Err(WsError::Create);
+        }
+        let raw = Context { api, ctx };
+        let state = Box::new(events::State::new(raw));
+        events::register(ctx, &state);
+        let (outbox_tx, outbox_rx) = mpsc::channel();
+        let ws = WebSocket {
+            raw,
+            state,
+            outbox_tx,
+            outbox_rx,
+            pending: Arc::default(),
+        };
+
+        // The shims are always installed so the connection registry stays
+        // accurate even when the user registers no handlers.
+        for (event, shim) in [
+            (Event::Open, events::on_open as *mut c_void),
+            (Event::Close, events::on_close as *mut c_void),
+            (Event::Frame, events::on_frame as *mut c_void),
+            (Event::Error, events::on_error as *mut c_void),
+        ] {
+            unsafe { ws.on_event(event, shim)? };
+        }
+        Ok(ws)
+    }
+
+    /// Version of the loaded native library.
+    ///
+    /// `None` if the library does not export `websocket_version`, which
+    /// older builds lack, or with the `static-link` feature, where the
+    /// symbol cannot be looked up optionally. No compatibility check is
+    /// possible then.
+    pub fn native_version(&self) -> Option<Version> {
+        self.raw.api.version()
+    }
+
+    /// Destroys the native context now rather than at the end of scope.
+    ///
+    /// Same as dropping the value; see [`shutdown`](Self::shutdown) for
+    /// closing connections cleanly first. The raw context pointer is never handed
+    /// out, so once it is destroyed nothing can reach it again; using the
+    /// context afterwards does not compile:
+    ///
+    /// ```compile_fail,E0382
+    /// # fn main() -> Result<(), ws::WsError> {
+    /// let ws = ws::WebSocket::new()?;
+    /// ws.destroy();
+    /// ws.operate();
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// [`Sender`]s outliving the context fail with [`WsError::ContextGone`].
+    pub fn destroy(self) {
+        drop(self);
+    }
+
+    /// Unregisters every `on_*` handler, dropping the closures and whatever
+    /// they captured.
+    ///
+    /// The context keeps running and its bookkeeping (connection registry,
+    /// close handshakes, limits) carries on; events simply reach no handler
+    /// until new ones are registered. Handlers are owned by the
+    /// `WebSocket`, not the native library, so dropping it releases them as
+    /// well and calling this first is never required. A handler that calls
+    /// this is itself kept, as it is still running.
+    pub fn clear_handlers(&self) {
+        self.state.clear_handlers();
+    }
+
+    /// Applies `settings` to the context.
+    pub fn setup(&self, settings: &Settings) -> Result<(), WsError> {
+        check(
+            settings.with_raw(|raw| unsafe { (self.raw.api.setup)(self.raw.ctx, raw) }),
+            || WsError::Setup,
+        )?;
+        self.state.message_limit.set(settings.message_limit());
+        self.state.limit_action.set(settings.limit_action());
+        self.state.idle_timeout.set(settings.idle_timeout());
+        self.state.idle_backoff_cap.set(settings.idle_backoff_cap());
+        self.state.close_timeout.set(settings.close_timeout());
+        self.state.endpoint.set(settings.endpoint());
+        self.state.strict_utf8.set(settings.strict_utf8());
+        self.state.strict_protocol.set(settings.strict_protocol());
+        #[cfg(feature = "frame-trace")]
+        self.state.frame_trace.set(settings.frame_trace());
+        self.state.max_connections.set(settings.max_connections());
+        self.state.connect_timeout.set(settings.connect_timeout());
+        self.state
+            .handshake_timeout
+            .set(settings.handshake_timeout());
+        self.state
+            .max_frames_per_sec
+            .set(settings.max_frames_per_sec());
+        sender::lock(&self.pending).high_water_mark = settings.send_high_water_mark();
+        Ok(())
+    }
+
+    /// Changes the keepalive ping interval of a running context.
+    ///
+    /// The native library has no entry point for a single setting, so this
+    /// updates `settings` and passes it to [`setup`](Self::setup) again;
+    /// `settings` should be the value the context was set up with. Open
+    /// connections are kept. The interval is held per context, not per
+    /// connection, so it applies to existing connections as well as new
+    /// ones, starting with the ping after the one already scheduled.
+    pub fn set_ping_interval(&self, settings: &mut Settings, ms: u32) -> Result<(), WsError> {
+        settings.set_ping_interval(ms)?;
+        self.setup(settings)
+    }
+
+    /// Starts listening on `host`:`port`.
+    ///
+    /// Pass `None` (port 0) to let the OS pick an ephemeral port. Returns the
+    /// port reported back by the native library.
+    ///
+    /// Only TCP is supported: `websocket_bind` takes a host and a port and
+    /// has no way to listen on a Unix domain socket. A host naming one, as
+    /// nginx does with `unix:/path`, fails with [`WsError::Unsupported`]; a
+    /// reverse proxy should forward to a loopback address instead, e.g.
+    /// `127.0.0.1`.
+    pub fn bind(&self, host: &CStr, port: Option<u16>) -> Result<u16, WsError> {
+        if host.to_bytes().starts_with(b"unix:") {
+            return Err(UNIX_SOCKETS);
+        }
+        self.open_or_bind(self.raw.api.bind, host, port, |host, port| WsError::Bind {
+            host,
+            port,
+        })
+    }
+
+    /// Connects to `host`:`port`.
+    ///
+    /// Returns the port reported back by the native library. With a
+    /// [`connect_timeout`](SettingsBuilder::connect_timeout), `host` is
+    /// resolved first, failing with [`WsError::Timeout`] if that takes too
+    /// long, and the native library is handed the resolved address; a
+    /// [`handshake_timeout`](SettingsBuilder::handshake_timeout) reports a
+    /// connect or upgrade that does not complete in time.
+    ///
+    /// The native library performs the upgrade and reports none of the
+    /// server's response: its headers, such as a session cookie or the
+    /// `Sec-WebSocket-Extensions` echo, cannot be read from Rust. Its
+    /// `Sec-WebSocket-Accept` header is checked against the key by the
+    /// native library, which generates the key itself and has no setting to
+    /// skip the check; a response that fails it never opens a connection,
+    /// so the check cannot be relaxed from Rust, even for debugging a
+    /// broken server.
+    pub fn open(&self, host: &CStr, port: u16) -> Result<u16, WsError> {
+        let resolved;
+        let host = match (self.state.connect_timeout.get(), host.to_str()) {
+            (Some(timeout), Ok(name)) => {
+                resolved = CString::new(resolve::within(name, port, timeout)?.to_string())?;
+                resolved.as_c_str()
+            }
+            _ => host,
+        };
+        // Queued first in case the native library completes the upgrade,
+        // and calls the open handler, before returning.
+        self.state
+            .handshakes
+            .borrow_mut()
+            .push_back(events::PendingOpen {
+                host: host.to_string_lossy().into_owned(),
+                port,
+                started: Instant::now(),
+            });
+        let opened = self.open_or_bind(self.raw.api.open, host, Some(port), |host, port| {
+            WsError::Open { host, port }
+        });
+        if opened.is_err() {
+            self.state.handshakes.borrow_mut().pop_back();
+        }
+        opened
+    }
+
+    /// Would listen on the Unix domain socket at `path`, but always fails
+    /// with [`WsError::Unsupported`]: the C API can only listen on TCP. See
+    /// [`bind`](Self::bind).
+    pub fn bind_unix(&self, path: impl AsRef<std::path::Path>) -> Result<(), WsError> {
+        let _ = path;
+        Err(UNIX_SOCKETS)
+    }
+
+    /// [`bind`](Self::bind) taking the host as a `&str`, e.g. one read from
+    /// configuration. A host containing a NUL byte fails with
+    /// [`WsError::InvalidString`].
+    pub fn bind_str(&self, host: &str, port: Option<u16>) -> Result<u16, WsError> {
+        self.bind(&CString::new(host)?, port)
+    }
+
+    /// [`open`](Self::open) taking the host as a `&str`, e.g. one read from
+    /// configuration. A host containing a NUL byte fails with
+    /// [`WsError::InvalidString`].
+    pub fn open_str(&self, host: &str, port: u16) -> Result<u16, WsError> {
+        self.open(&CString::new(host)?, port)
+    }
+
+    fn open_or_bind(
+        &self,
+        call: unsafe extern "C" fn(
+            *mut c_void,
+            *const c_char,
+            *const c_char,
+            *mut c_int,
+        ) -> e_ws_status,
+        host: &CStr,
+        port: Option<u16>,
+        err: impl FnOnce(String, u16) -> WsError,
+    ) -> Result<u16, WsError> {
+        let port = port.unwrap_or(0);
+        let port_str = CString::new(port.to_string())?;
+        // Seed with the requested port in case the library leaves it untouched.
+        let mut out = c_int::from(port);
+        check(
+            unsafe { call(self.raw.ctx, host.as_ptr(), port_str.as_ptr(), &mut out) },
+            || err(host.to_string_lossy().into_owned(), port),
+        )?;
+        Ok(u16::try_from(out).unwrap_or(port))
+    }
+
+    /// Runs one iteration of the native event loop.
+    ///
+    /// Frames queued through a [`Sender`] are flushed first, and messages
+    /// reassembled during the iteration are delivered to
+    /// [`on_message`](Self::on_message) at the end. Returns `false` once the
+    /// context has nothing left to do.
+    ///
+    /// The C API exposes no fd to wait on, neither the listening socket nor
+    /// a connection's, so the context cannot be registered with an external
+    /// epoll or kqueue loop. Embed it by calling
+    /// [`operate_with_timeout`](Self::operate_with_timeout) from that loop's
+    /// timer or idle hook, or run it on a thread of its own with
+    /// [`spawn`](Self::spawn).
+    pub fn operate(&self) -> bool {
+        self.operate_once() != OperateOutcome::Stopped
+    }
+
+    /// Runs one iteration of the event loop and reports whether it did work.
+    ///
+    /// The native `operate` only says whether to keep going, so an iteration
+    /// counts as [`Active`](OperateOutcome::Active) when a callback fired or
+    /// [`Sender`] frames were flushed. Otherwise the call sleeps for
+    /// `idle_sleep` and returns [`Idle`](OperateOutcome::Idle), which keeps a
+    /// plain `while` loop from spinning a core at 100% while nothing happens,
+    /// at the cost of up to `idle_sleep` extra latency for the next event.
+    /// The `idle_cpu` bench compares it with a bare `operate` loop on an
+    /// idle server.
+    pub fn operate_with_timeout(&self, idle_sleep: Duration) -> OperateOutcome {
+        let outcome = self.operate_once();
+        if outcome == OperateOutcome::Idle {
+            thread::sleep(idle_sleep);
+        }
+        outcome
+    }
+
+    /// Runs event loop iterations until `max_events` connections have opened
+    /// or frames arrived, or an iteration finds nothing to do, then returns
+    /// control to the caller.
+    ///
+    /// Meant for sharing a thread with other work: unlike
+    /// [`operate_with_timeout`](Self::operate_with_timeout) it never sleeps,
+    /// and a busy context cannot keep it running indefinitely. The count is
+    /// checked between iterations, because a single native iteration
+    /// services every socket that is ready and cannot be stopped partway;
+    /// a call can therefore overshoot `max_events` by what its last
+    /// iteration handled. Whatever is still waiting in the sockets is
+    /// serviced on the next call. Returns [`Idle`](OperateOutcome::Idle)
+    /// if no iteration did any work.
+    pub fn operate_n(&self, max_events: usize) -> OperateOutcome {
+        let start = self.state.serviced.get();
+        let mut outcome = OperateOutcome::Idle;
+        loop {
+            match self.operate_once() {
+                OperateOutcome::Stopped => return OperateOutcome::Stopped,
+                OperateOutcome::Idle => return outcome,
+                OperateOutcome::Active => outcome = OperateOutcome::Active,
+            }
+            if self.state.serviced.get().wrapping_sub(start) >= max_events {
+                return outcome;
+            }
+        }
+    }
+
+    /// Returns the next event, running the event loop until one arrives.
+    ///
+    /// An alternative to the `on_*` handlers for code that would rather pull
+    /// events in a plain loop:
+    ///
+    /// ```no_run
+    /// # fn main() -> Result<(), ws::WsError> {
+    /// # let ws = ws::WebSocket::new()?;
+    /// while let Some(event) = ws.operate_poll() {
+    ///     println!("{event:?}");
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// Events are queued from the first call on, and handlers registered
+    /// with the `on_*` methods still run alongside. Idle iterations back off
+    /// like [`run`](Self::run). Returns `None` once the context has stopped
+    /// and every queued event has been returned.
+    ///
+    /// Raw callbacks registered with [`on_event`](Self::on_event) replace
+    /// the shims that queue events, so do not combine the two for the same
+    /// event.
+    pub fn operate_poll(&self) -> Option<PolledEvent> {
+        let mut backoff = IdleBackoff::new(self.state.idle_backoff_cap.get());
+        loop {
+            if let Some(event) = self
+                .state
+                .polled
+                .borrow_mut()
+                .get_or_insert_default()
+                .pop_front()
+            {
+                return Some(event);
+            }
+            match self.operate_once() {
+                OperateOutcome::Stopped => {
+                    return self.state.polled.borrow_mut().as_mut()?.pop_front();
+                }
+                OperateOutcome::Active => backoff.reset(),
+                OperateOutcome::Idle => backoff.wait(),
+            }
+        }
+    }
+
+    pub(crate) fn operate_once(&self) -> OperateOutcome {
+        self.state.active.set(false);
+        let flushed = self.flush_outbox();
+        let running = unsafe { (self.raw.api.operate)(self.raw.ctx) };
+        self.state.flush_messages();
+        self.state.close_idle();
+        self.state.expire_handshakes();
+        self.state.close_stalled();
+        self.state.flush_coalesced();
+
+        if !running {
+            OperateOutcome::Stopped
+        } else if flushed || self.state.active.get() {
+            OperateOutcome::Active
+        } else {
+            OperateOutcome::Idle
+        }
+    }
+
+    /// Returns a handle that can queue frames from other threads.
+    pub fn sender(&self) -> Sender {
+        Sender {
+            tx: self.outbox_tx.clone(),
+            pending: Arc::clone(&self.pending),
+        }
+    }
+
+    /// Emits every frame queued by [`Sender`]s. Failures are reported to the
+    /// error handler since there is no caller to return them to.
+    /// Returns whether anything was queued.
+    fn flush_outbox(&self) -> bool {
+        let mut flushed = false;
+        while let Ok(out) = self.outbox_rx.try_recv() {
+            flushed = true;
+            let size: usize = out.frames.iter().map(Frame::len).sum();
+            {
+                let mut pending = sender::lock(&self.pending);
+                if let Some(bytes) = pending.bytes.get_mut(&out.id) {
+                    *bytes = bytes.saturating_sub(size);
+                    if *bytes == 0 {
+                        pending.bytes.remove(&out.id);
+                    }
+                }
+            }
+            if let Err(e) = self.state.send_batch(out.id, &out.frames) {
+                self.state.report(&e);
+            }
+        }
+
+        let refused: Vec<(ConnectionId, usize)> =
+            sender::lock(&self.pending).refused.drain().collect();
+        for (id, bytes) in refused {
+            events::dispatch(&self.state.backpressure, |h| h(id, bytes));
+            if self.pending_bytes(id) == 0 {
+                events::dispatch(&self.state.drain, |h| h(id));
+            }
+        }
+        flushed
+    }
+
+    /// Bytes queued for `id` through [`Sender`]s that have not been handed
+    /// to the native library yet.
+    ///
+    /// Bytes already passed to the native library are not counted: the C
+    /// API does not report how much it still has buffered for a socket.
+    pub fn pending_bytes(&self, id: ConnectionId) -> usize {
+        sender::lock(&self.pending)
+            .bytes
+            .get(&id)
+            .copied()
+            .unwrap_or(0)
+    }
+
+    /// Runs the event loop until Ctrl+C is pressed or the context stops.
+    ///
+    /// The first Ctrl+C makes this return so the caller can drop the context
+    /// cleanly; pressing it again after that force-exits the process.
+    pub fn run_until_ctrl_c(&self) -> Result<(), WsError> {
+        shutdown::install_ctrl_c_handler()?;
+        shutdown::reset();
+        self.run_while(|| !shutdown::interrupted());
+        Ok(())
+    }
+
+    /// Runs the event loop until the context stops.
+    ///
+    /// Unlike a bare `while ws.operate() {}`, idle iterations back off: the
+    /// loop yields, then sleeps for a doubling interval up to the settings'
+    /// [`idle_backoff_cap`](SettingsBuilder::idle_backoff_cap), and returns
+    /// to full speed as soon as there is activity. This keeps an idle
+    /// context from pinning a core, as the `idle_cpu` bench shows for an
+    /// idle server.
+    pub fn run(&self) {
+        self.run_while(|| true);
+    }
+
+    fn run_while(&self, mut keep_going: impl FnMut() -> bool) {
+        let mut backoff = IdleBackoff::new(self.state.idle_backoff_cap.get());
+        while keep_going() {
+            match self.operate_once() {
+                OperateOutcome::Stopped => break,
+                OperateOutcome::Active => backoff.reset(),
+                OperateOutcome::Idle => backoff.wait(),
+            }
+        }
+    }
+
+    /// Stops accepting connections and keeps servicing the open ones until
+    /// they have all closed or `timeout` elapses.
+    ///
+    /// Connections opening from now on are closed straight away with
+    /// [`ClosureStatus::GoingAway`], unseen by the open and close handlers.
+    /// Connections still open at the deadline are sent a `GoingAway` close
+    /// frame, which one more iteration flushes; the rest of their close
+    /// handshake happens on later [`operate`](Self::operate) calls. Returns
+    /// how many connections had to be closed that way.
+    ///
+    /// The context keeps refusing new connections afterwards.
+    pub fn drain(&self, timeout: Duration) -> usize {
+        self.state.draining.set(true);
+        let deadline = Instant::now() + timeout;
+        self.run_while(|| self.connection_count() > 0 && Instant::now() < deadline);
+
+        let remaining: Vec<ConnectionId> =
+            self.state.connections.borrow().keys().copied().collect();
+        for &id in &remaining {
+            match self.close(id, ClosureStatus::GoingAway, "server shutting down") {
+                Ok(()) | Err(WsError::ConnectionClosed { .. }) => {}
+                Err(e) => self.state.report(&e),
+            }
+        }
+        if !remaining.is_empty() {
+            self.operate_once();
+        }
+        remaining.len()
+    }
+
+    /// Closes every connection with `status` and `reason`, services the
+    /// close handshakes until they complete or `timeout` elapses, then
+    /// destroys the context. Returns how many connections were still open
+    /// when it was destroyed.
+    ///
+    /// This is the graceful counterpart to dropping the `WebSocket` (or
+    /// [`destroy`](Self::destroy)), which hands the context to
+    /// `websocket_destroy` straight away: whatever that does with open
+    /// connections, peers get no close frame from Rust and typically see
+    /// [`ClosureStatus::Abnormal`]. Here every peer is sent a close frame
+    /// first and gets the chance to answer it, and connections opening in
+    /// the meantime are refused with [`ClosureStatus::GoingAway`]. Handlers
+    /// keep running until the end, so the close handler sees each
+    /// connection close.
+    ///
+    /// Fails with [`WsError::InvalidCloseStatus`] or
+    /// [`WsError::ControlFrameTooLarge`] before closing anything if `status`
+    /// or `reason` cannot be sent; the context is destroyed either way.
+    pub fn shutdown(
+        self,
+        status: ClosureStatus,
+        reason: &str,
+        timeout: Duration,
+    ) -> Result<usize, WsError> {
+        if !status.is_sendable() {
+            return Err(WsError::InvalidCloseStatus {
+                code: status.code(),
+            });
+        }
+        check_control_payload(&[&[0; 2], reason.as_bytes()].concat())?;
+
+        self.state.draining.set(true);
+        let open: Vec<ConnectionId> = self.state.connections.borrow().keys().copied().collect();
+        for id in open {
+            match self.close(id, status, reason) {
+                Ok(()) | Err(WsError::ConnectionClosed { .. }) => {}
+                Err(e) => self.state.report(&e),
+            }
+        }
+        let deadline = Instant::now() + timeout;
+        self.run_while(|| self.connection_count() > 0 && Instant::now() < deadline);
+        Ok(self.connection_count())
+    }
+
+    /// Returns the context to the state it was in right after
+    /// [`setup`](Self::setup), so a client can [`open`](Self::open) again
+    /// without creating a new context.
+    ///
+    /// Connections still open are dropped without waiting for a close
+    /// handshake: each is sent a [`ClosureStatus::GoingAway`] close frame
+    /// and the close handler sees [`ClosureStatus::Abnormal`] straight away.
+    /// The C API cannot drop a connection outright, so the native library
+    /// keeps it until the peer answers or goes away, but nothing it reports
+    /// about it reaches the handlers. Everything kept about connections is
+    /// then forgotten: partial messages, close handshakes in progress,
+    /// frames queued through [`Sender`]s, events not yet returned by
+    /// [`operate_poll`](Self::operate_poll) and the status of the
+    /// last close. A
+    /// [`drain`](Self::drain) is cancelled, so connections are accepted
+    /// again.
+    ///
+    /// Registered handlers persist, and so do the applied settings, both in
+    /// the native library, which keeps what `setup` gave it, and on the Rust
+    /// side; call `setup` again to change them. A server's listening socket
+    /// is not affected.
+    pub fn reset(&self) {
+        self.state.reset();
+        while self.outbox_rx.try_recv().is_ok() {}
+        let mut pending = sender::lock(&self.pending);
+        pending.bytes.clear();
+        pending.refused.clear();
+    }
+
+    /// Sends `text` as a single text frame to `id`.
+    pub fn send_text(&self, id: ConnectionId, text: &str) -> Result<(), WsError> {
+        self.send(id, Opcode::Text, text.as_bytes())
+    }
+
+    /// Sends `data` as a single binary frame to `id`.
+    pub fn send_binary(&self, id: ConnectionId, data: &[u8]) -> Result<(), WsError> {
+        self.send(id, Opcode::Binary, data)
+    }
+
+    /// Emits a prebuilt `frame` to `id`. The frame can be emitted again.
+    pub fn emit(&self, id: ConnectionId, frame: &Frame) -> Result<(), WsError> {
+        self.state.send_batch(id, core::slice::from_ref(frame))
+    }
+
+    /// Sends `frames` to `id` back to back, with no other frame in between.
+    ///
+    /// All sends on a context happen on the thread running its event loop,
+    /// either directly or by [`operate`](Self::operate) flushing what
+    /// [`Sender`]s queued, so nothing can interleave with a batch while it is
+    /// emitted. Control frames are size-checked before the first is emitted;
+    /// if one is too large, nothing is sent. If an emit fails, the remaining
+    /// frames are not sent and the error is returned. Use
+    /// [`close`](Self::close) rather than a close frame in a batch.
+    pub fn send_batch(&self, id: ConnectionId, frames: &[Frame]) -> Result<(), WsError> {
+        self.state.send_batch(id, frames)
+    }
+
+    /// Sends `text` to every open connection.
+    ///
+    /// The frame is built once and emitted to each connection in turn. A failed emit,
+    /// typically a client that closed mid-broadcast, is skipped rather than
+    /// aborting the loop. Returns the number of connections the frame was
+    /// emitted to.
+    ///
+    /// Connections whose emit failed are dropped once the loop is done: the
+    /// close handler is called with [`ClosureStatus::Abnormal`], so the next
+    /// broadcast does not try them again. The native library, which owns
+    /// their sockets, releases them once it notices they are gone.
+    pub fn broadcast_text(&self, text: &str) -> Result<usize, WsError> {
+        let frame = self.raw.frame(Opcode::Text, text.as_bytes())?;
+
+        // Snapshot the ids: emitting may re-enter the close shim, which
+        // mutates the registry.
+        let closing = self.state.closing.borrow();
+        let ids: Vec<ConnectionId> = self
+            .state
+            .connections
+            .borrow()
+            .keys()
+            .copied()
+            .filter(|id| !closing.contains_key(id))
+            .collect();
+        drop(closing);
+        let (sent, dead): (Vec<ConnectionId>, Vec<ConnectionId>) =
+            ids.into_iter().partition(|&id| {
+                self.state
+                    .emit(id, Opcode::Text, text.len(), &frame)
+                    .is_ok()
+            });
+        // Only now that nothing is iterating the registry.
+        for id in dead {
+            if self.state.connections.borrow().contains_key(&id) {
+                ws_log!(info, fd = id.as_raw(); "dropping connection after failed broadcast");
+                self.state.abandon(id);
+            }
+        }
+        Ok(sent.len())
+    }
+
+    /// Turns outbound frame coalescing for `id` on, with `window` as the
+    /// longest a frame may be held back, or off with `None`. Off by default.
+    ///
+    /// While it is on, consecutive text or binary frames sent to `id` with
+    /// [`send_text`](Self::send_text), [`send_binary`](Self::send_binary),
+    /// [`Connection::send_text`] or [`Connection::send_binary`] are held back
+    /// and concatenated into a single frame, which goes out once `window` has
+    /// passed since the first of them, when a frame of another opcode or any
+    /// other frame is sent to `id`, or on [`flush`](Self::flush). That saves
+    /// a frame header and usually a write per message at high message
+    /// rates, as the `coalesce` bench counts: bursts of 100 messages go out
+    /// as one emit each. But it merges message boundaries: the peer
+    /// receives one message holding several, so only enable it for
+    /// connections whose protocol delimits records itself, e.g.
+    /// newline-separated JSON. A
+    /// held frame is emitted by [`operate`](Self::operate), so an error is
+    /// reported to the error handler rather than returned. Turning
+    /// coalescing off flushes what is held. Fails with
+    /// [`WsError::NotConnected`] if `id` is not open.
+    pub fn set_coalesce_window(
+        &self,
+        id: ConnectionId,
+        window: Option<Duration>,
+    ) -> Result<(), WsError> {
+        if !self.state.connections.borrow().contains_key(&id) {
+            return Err(WsError::NotConnected { id });
+        }
+        self.state.flush(id)?;
+        let mut coalescing = self.state.coalescing.borrow_mut();
+        match window {
+            Some(window) => coalescing.insert(id, coalesce::Coalescer::new(window)),
+            None => coalescing.remove(&id),
+        };
+        Ok(())
+    }
+
+    /// Overrides the settings' `message_limit` for `id`, e.g. to let a client
+    /// send larger messages once it has authenticated. Fails with
+    /// [`WsError::NotConnected`] if `id` is not open.
+    ///
+    /// The limit applies to every frame and reassembled message delivered
+    /// from then on, including a message already in progress. The native
+    /// library only knows the settings' limit and refuses single frames over
+    /// it itself, so a higher override admits larger messages only when the
+    /// peer fragments them into frames within the settings' limit.
+    pub fn set_message_limit(&self, id: ConnectionId, bytes: usize) -> Result<(), WsError> {
+        self.state.set_message_limit(id, bytes)
+    }
+
+    /// Sends the frame held back for coalescing on `id` straight away, if
+    /// there is one. See [`set_coalesce_window`](Self::set_coalesce_window).
+    pub fn flush(&self, id: ConnectionId) -> Result<(), WsError> {
+        self.state.flush(id)
+    }
+
+    /// Enables or disables Nagle's algorithm (`TCP_NODELAY`) on `id`'s
+    /// socket. Disabling it sends small frames straight away at the cost of
+    /// more packets.
+    ///
+    /// Applies to that connection only. Unix only: on Windows the native
+    /// library does not report a usable socket handle, and this fails with
+    /// [`WsError::SocketOption`] wrapping [`io::ErrorKind::Unsupported`].
+    ///
+    /// Best effort: the socket belongs to the native library, which is not
+    /// told about the change and may set the option again itself. Only the
+    /// option is touched; the socket is never read, written or closed.
+    ///
+    /// [`io::ErrorKind::Unsupported`]: std::io::ErrorKind::Unsupported
+    pub fn set_nodelay(&self, id: ConnectionId, enabled: bool) -> Result<(), WsError> {
+        self.set_flag(id, socket::NODELAY, enabled)
+    }
+
+    /// Whether `TCP_NODELAY` is set on `id`'s socket.
+    pub fn nodelay(&self, id: ConnectionId) -> Result<bool, WsError> {
+        self.flag(id, socket::NODELAY)
+    }
+
+    /// Enables or disables TCP keepalive probes (`SO_KEEPALIVE`) on `id`'s
+    /// socket, with the system's default timings.
+    ///
+    /// Applies to that connection only, with the same platform limits as
+    /// [`set_nodelay`](Self::set_nodelay). Unlike WebSocket pings, the
+    /// probes are invisible to the peer application.
+    pub fn set_keepalive(&self, id: ConnectionId, enabled: bool) -> Result<(), WsError> {
+        self.set_flag(id, socket::KEEPALIVE, enabled)
+    }
+
+    /// Whether `SO_KEEPALIVE` is set on `id`'s socket.
+    pub fn keepalive(&self, id: ConnectionId) -> Result<bool, WsError> {
+        self.flag(id, socket::KEEPALIVE)
+    }
+
+    fn set_flag(&self, id: ConnectionId, flag: socket::Flag, enabled: bool) -> Result<(), WsError> {
+        if !self.state.connections.borrow().contains_key(&id) {
+            return Err(WsError::NotConnected { id });
+        }
+        socket::set(id.as_raw(), flag, enabled).map_err(|source| WsError::SocketOption {
+            id,
+            option: flag.label,
+            source,
+        })
+    }
+
+    fn flag(&self, id: ConnectionId, flag: socket::Flag) -> Result<bool, WsError> {
+        if !self.state.connections.borrow().contains_key(&id) {
+            return Err(WsError::NotConnected { id });
+        }
+        socket::get(id.as_raw(), flag).map_err(|source| WsError::SocketOption {
+            id,
+            option: flag.label,
+            source,
+        })
+    }
+
+    /// Number of currently open connections.
+    pub fn connection_count(&self) -> usize {
+        self.state.connections.borrow().len()
+    }
+
+    /// Snapshot of the currently open connections, in no particular order.
+    ///
+    /// The snapshot is taken up front, so it is safe to send to or close the
+    /// listed connections while iterating.
+    pub fn connections(&self) -> impl Iterator<Item = ConnectionInfo> {
+        let connections: Vec<ConnectionInfo> =
+            self.state.connections.borrow().values().cloned().collect();
+        connections.into_iter()
+    }
+
+    /// Metadata for the open connection `id`, if there is one.
+    pub fn connection(&self, id: ConnectionId) -> Option<ConnectionInfo> {
+        self.state.connections.borrow().get(&id).cloned()
+    }
+
+    /// Traffic counters of the open connection `id`, if there is one.
+    pub fn connection_stats(&self, id: ConnectionId) -> Option<ConnectionStats> {
+        self.state.stats.borrow().get(&id).cloned()
+    }
+
+    /// When the open connection `id` last received a frame of any kind,
+    /// pongs included, or opened if it has received none; `None` if it is
+    /// not open.
+    ///
+    /// [`Instant`] is a monotonic clock, so the value is unaffected by changes
+    /// to the system time; compare it with `Instant::now()` to find
+    /// connections idle for too long, e.g. to prune them with
+    /// [`close`](Self::close). Unlike the
+    /// [`idle_timeout`](SettingsBuilder::idle_timeout), keepalive traffic
+    /// counts as activity.
+    pub fn last_activity(&self, id: ConnectionId) -> Option<Instant> {
+        self.state.last_activity.borrow().get(&id).copied()
+    }
+
+    /// Attaches application data to the open connection `id`, replacing and
+    /// dropping any data attached before.
+    ///
+    /// The data is dropped when the connection closes, after the close
+    /// handler has run, or when the context is dropped. Fails with
+    /// [`WsError::NotConnected`] if `id` is not open.
+    pub fn set_user_data(&self, id: ConnectionId, data: Box<dyn Any>) -> Result<(), WsError> {
+        if !self.state.connections.borrow().contains_key(&id) {
+            return Err(WsError::NotConnected { id });
+        }
+        self.state.user_data.borrow_mut().insert(id, data);
+        Ok(())
+    }
+
+    /// Borrows the data attached to `id`, if there is some of type `T`.
+    ///
+    /// Release the guard before calling [`operate`](Self::operate); a
+    /// connection closing while it is held would panic.
+    pub fn get_user_data<T: Any>(&self, id: ConnectionId) -> Option<RefMut<'_, T>> {
+        RefMut::filter_map(self.state.user_data.borrow_mut(), |data| {
+            data.get_mut(&id)?.downcast_mut::<T>()
+        })
+        .ok()
+    }
+
+    /// Sends a ping with `payload` to `id`.
+    ///
+    /// Control frame payloads are limited to 125 bytes.
+    pub fn ping(&self, id: ConnectionId, payload: &[u8]) -> Result<(), WsError> {
+        check_control_payload(payload)?;
+        self.send(id, Opcode::Ping, payload)
+    }
+
+    /// Starts the close handshake on `id` with `status` and a UTF-8 `reason`.
+    ///
+    /// The reason may be at most 123 bytes so the frame fits the control
+    /// payload limit. Once the close frame is sent, further sends on `id`
+    /// fail with [`WsError::ConnectionClosed`].
+    pub fn close(
+        &self,
+        id: ConnectionId,
+        status: ClosureStatus,
+        reason: &str,
+    ) -> Result<(), WsError> {
+        self.state.close(id, status, reason)
+    }
+
+    /// Starts the close handshake on `id` with an empty close frame, carrying
+    /// no status code, for peers that expect one. The peer sees
+    /// [`ClosureStatus::NoStatus`].
+    pub fn close_bare(&self, id: ConnectionId) -> Result<(), WsError> {
+        self.state.close_bare(id)
+    }
+
+    fn send(&self, id: ConnectionId, opcode: Opcode, data: &[u8]) -> Result<(), WsError> {
+        self.state.send(id, opcode, data)
+    }
+
+    /// Registers a closure called when a connection opens, with a
+    /// [`Connection`] handle and the parsed peer address. The address is
+    /// `None` when the native library reports none or it cannot be parsed.
+    ///
+    /// The native open callback carries nothing from the handshake itself,
+    /// and the C API has no event before it: the upgrade request's path,
+    /// query and headers cannot be read, nor a handshake rejected from
+    /// Rust. Authenticate in the first message instead, and close the
+    /// connection if it does not check out. Nor is there subprotocol
+    /// negotiation: the C settings have no `Sec-WebSocket-Protocol` field,
+    /// so a client cannot offer one and a server never selects or echoes
+    /// one. Protocols that need it should agree on a version in their first
+    /// message as well.
+    ///
+    /// On a secured connection the handler runs once both the TLS and the
+    /// WebSocket handshake have completed. The C API reports neither the
+    /// TLS handshake on its own nor the negotiated protocol version or
+    /// cipher suite; a failed TLS handshake only shows up as an error, with
+    /// [`ErrorKind::Tls`] in [`on_error_detailed`](Self::on_error_detailed).
+    pub fn on_open(&self, handler: impl FnMut(Connection<'_>, Option<&PeerAddr>) + Send + 'static) {
+        *self.state.open.borrow_mut() = Some(Box::new(handler));
+    }
+
+    /// Registers a closure called when a connection closes, with its status
+    /// and the reason the peer gave, if any.
+    ///
+    /// The status is the one in the peer's close frame, or
+    /// [`ClosureStatus::NoStatus`] (1005) if that frame was empty, as after
+    /// [`close_bare`](Self::close_bare); without a close frame from the peer
+    /// it is the status the native library reports, typically
+    /// [`ClosureStatus::Abnormal`]. The reason is `None` when the peer's close
+    /// frame carried only a status code, or a reason that is not valid UTF-8,
+    /// and when it sent no close frame at all.
+    pub fn on_close(
+        &self,
+        handler: impl FnMut(ConnectionId, ClosureStatus, Option<String>) + Send + 'static,
+    ) {
+        *self.state.close.borrow_mut() = Some(Box::new(handler));
+    }
+
+    /// Registers a closure called for every received frame, with a
+    /// [`Connection`] handle to reply on.
+    ///
+    /// The payload slice points into the native library's receive buffer
+    /// and is only valid for the duration of the call; copy it (`to_vec`)
+    /// to keep it, or see [`on_frame_bytes`](Self::on_frame_bytes).
+    pub fn on_frame(&self, handler: impl FnMut(Connection<'_>, Opcode, &[u8]) + Send + 'static) {
+        *self.state.frame.borrow_mut() = Some(Box::new(handler));
+    }
+
+    /// Registers a closure called for every received frame with the payload
+    /// as [`Bytes`](bytes::Bytes), alongside any [`on_frame`](Self::on_frame)
+    /// handler.
+    ///
+    /// The payload is copied out of the native buffer once per frame, and
+    /// only while this handler is registered; clones of the `Bytes` share
+    /// that copy, so it can be handed to other tasks or threads cheaply.
+    #[cfg(feature = "bytes")]
+    pub fn on_frame_bytes(
+        &self,
+        handler: impl FnMut(Connection<'_>, Opcode, bytes::Bytes) + Send + 'static,
+    ) {
+        *self.state.frame_bytes.borrow_mut() = Some(Box::new(handler));
+    }
+
+    /// Swaps the [`on_frame`](Self::on_frame) handler, e.g. once a
+    /// connection has authenticated, and returns whether one was replaced.
+    ///
+    /// Frames from the next one on go to `handler`; the old handler is
+    /// dropped.
+    pub fn replace_frame_handler(
+        &self,
+        handler: impl FnMut(Connection<'_>, Opcode, &[u8]) + Send + 'static,
+    ) -> bool {
+        self.state
+            .frame
+            .borrow_mut()
+            .replace(Box::new(handler))
+            .is_some()
+    }
+
+    /// Registers a closure called with each complete text or binary message.
+    ///
+    /// Continuation frames are appended to the message they continue, and
+    /// the combined size is held to the settings' `message_limit`; a message
+    /// that grows past it is reported through
+    /// [`on_limit_exceeded`](Self::on_limit_exceeded) and handled as the
+    /// [`limit_action`](SettingsBuilder::limit_action) says. A continuation with
+    /// no message in progress is reported to the error handler and closes
+    /// the connection with [`ClosureStatus::ProtocolError`].
+    ///
+    /// The native library does not report the FIN bit, so a message is
+    /// delivered when the next one starts on the same connection, when the
+    /// connection closes, or at the end of the [`operate`](Self::operate)
+    /// call that received it, whichever comes first. [`on_frame`](Self::on_frame)
+    /// still sees every individual frame.
+    pub fn on_message(&self, handler: impl FnMut(Connection<'_>, Opcode, &[u8]) + Send + 'static) {
+        *self.state.message.borrow_mut() = Some(Box::new(handler));
+    }
+
+    /// Registers a closure called when a pong arrives, e.g. to measure
+    /// round-trip time for [`ping`](Self::ping).
+    ///
+    /// Incoming pings are answered automatically with a pong echoing their
+    /// payload, whether or not a handler is registered.
+    pub fn on_pong(&self, handler: impl FnMut(ConnectionId, &[u8]) + Send + 'static) {
+        *self.state.pong.borrow_mut() = Some(Box::new(handler));
+    }
+
+    /// Registers a closure called when a peer sends a frame larger than the
+    /// configured `message_limit`, with the connection id and the frame size.
+    ///
+    /// After the closure returns, the
+    /// [`limit_action`](SettingsBuilder::limit_action) is applied: by
+    /// default the frame is dropped without reaching
+    /// [`on_frame`](Self::on_frame) and the connection is closed with
+    /// [`ClosureStatus::MessageTooBig`] unless the closure already closed it.
+    pub fn on_limit_exceeded(&self, handler: impl FnMut(ConnectionId, usize) + Send + 'static) {
+        *self.state.limit_exceeded.borrow_mut() = Some(Box::new(handler));
+    }
+
+    /// Registers a closure called on the event loop thread when a [`Sender`]
+    /// send to a connection was refused with [`WsError::Backpressure`], with
+    /// the connection id and the bytes that were queued at the time.
+    pub fn on_backpressure(&self, handler: impl FnMut(ConnectionId, usize) + Send + 'static) {
+        *self.state.backpressure.borrow_mut() = Some(Box::new(handler));
+    }
+
+    /// Registers a closure called once a connection that had a [`Sender`]
+    /// send refused has had its queue emitted, so producers can resume.
+    pub fn on_drain(&self, handler: impl FnMut(ConnectionId) + Send + 'static) {
+        *self.state.drain.borrow_mut() = Some(Box::new(handler));
+    }
+
+    /// Registers a closure called when the native library reports an error.
+    ///
+    /// Errors raised by this crate are reported here too. That includes a
+    /// handler panicking inside a native callback: a panic cannot unwind
+    /// into the native library, so it is caught, reported as
+    /// [`WsError::HandlerPanicked`], and the connection concerned is closed
+    /// with [`ClosureStatus::InternalError`].
+    pub fn on_error(&self, handler: impl FnMut(&str) + Send + 'static) {
+        *self.state.error.borrow_mut() = Some(Box::new(handler));
+    }
+
+    /// Registers a closure called for the same errors as
+    /// [`on_error`](Self::on_error), with the connection concerned (`None`
+    /// for listener-level errors) and an [`ErrorKind`].
+    ///
+    /// Errors raised by this crate, such as [`WsError::TooManyConnections`]
+    /// or [`WsError::InvalidUtf8`], carry their connection and exact kind.
+    /// The native library only reports a message, so its errors come with
+    /// `None` and a kind guessed by [`ErrorKind::classify`]. Both handlers
+    /// are called when both are registered.
+    pub fn on_error_detailed(
+        &self,
+        handler: impl FnMut(Option<ConnectionId>, ErrorKind, &str) + Send + 'static,
+    ) {
+        *self.state.error_detailed.borrow_mut() = Some(Box::new(handler));
+    }
+
+    /// Status of the most recent connection close on this context.
+    pub(crate) fn last_close_status(&self) -> Option<ClosureStatus> {
+        self.state.last_close.get()
+    }
+
+    /// Registers a raw callback for `event`.
+    ///
+    /// Prefer the closure-based `on_*` methods; this is for callbacks that
+    /// need the raw arguments. Replacing the `open` or `close` callback this way bypasses the
+    /// connection registry, so [`broadcast_text`](Self::broadcast_text) will
+    /// no longer see new connections.
+    ///
+    /// # Safety
+    ///
+    /// `callback` must be an `extern "C"` function whose signature matches
+    /// the one documented on `event`.
+    pub unsafe fn on_event(&self, event: Event, callback: *mut c_void) -> Result<(), WsError> {
+        unsafe { self.on(event.name(), callback) }
+    }
+
+    /// Registers a raw callback for an event by name.
+    ///
+    /// An escape hatch for native events [`Event`] does not cover; a
+    /// misspelt name only shows up as [`WsError::Register`] at runtime. The
+    /// same caveats as [`on_event`](Self::on_event) apply.
+    ///
+    /// # Safety
+    ///
+    /// `callback` must be an `extern "C"` function whose signature matches
+    /// the one the native library expects for `event`.
+    pub unsafe fn on(&self, event: &CStr, callback: *mut c_void) -> Result<(), WsError> {
+        check(
+            unsafe { (self.raw.api.on)(self.raw.ctx, event.as_ptr(), callback) },
+            || WsError::Register {
+                event: event.to_string_lossy().into_owned(),
+            },
+        )
+    }
+}
+
+/// Maximum payload of a control frame (RFC 6455, section 5.5).
+pub const MAX_CONTROL_PAYLOAD: usize = 125;
+
+/// What [`WebSocket::bind_unix`] and a `unix:` host fail with.
+const UNIX_SOCKETS: WsError = WsError::Unsupported {
+    what: "Unix domain sockets",
+};
+
+fn check_control_payload(payload: &[u8]) -> Result<(), WsError> {
+    if payload.len() > MAX_CONTROL_PAYLOAD {
+        return Err(WsError::ControlFrameTooLarge { len: payload.len() });
+    }
+    Ok(())
+}
+
+impl Drop for WebSocket {
+    fn drop(&mut self) {
+        // Handlers stay installed while the native context is destroyed, so
+        // events it fires on the way out are still delivered. They are owned
+        // by `state`, never by the native side, and are dropped right after.
+        unsafe { (self.raw.api.destroy)(self.raw.ctx) };
+        events::unregister(self.raw.ctx);
+        self.state.clear_handlers();
+    }
+}
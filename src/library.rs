@@ -0,0 +1,81 @@
+use crate::WsError;
+use std::env;
+use std::path::PathBuf;
+
+#[cfg(target_os = "windows")]
+pub(crate) const LIB_NAME: &str = "Websocket.dll";
+#[cfg(target_os = "linux")]
+pub(crate) const LIB_NAME: &str = "Websocket.so";
+#[cfg(target_os = "macos")]
+pub(crate) const LIB_NAME: &str = "Websocket.dylib";
+
+/// Environment variable that overrides library discovery. It may name the
+/// library file itself or the directory containing it.
+pub const LIB_PATH_ENV: &str = "WEBSOCKET_LIB_PATH";
+
+#[cfg(target_os = "windows")]
+const SEARCH_PATH_ENV: &str = "PATH";
+#[cfg(target_os = "linux")]
+const SEARCH_PATH_ENV: &str = "LD_LIBRARY_PATH";
+#[cfg(target_os = "macos")]
+const SEARCH_PATH_ENV: &str = "DYLD_LIBRARY_PATH";
+
+/// Directory `build.rs` copied the native library into, if it found a
+/// build of it under the submodule at compile time.
+pub const BUNDLED_LIB_DIR: Option<&str> = option_env!("WEBSOCKET_LIB_DIR");
+
+/// Every location checked by [`find_library`], in order.
+fn candidates() -> Vec<PathBuf> {
+    let mut paths = Vec::new();
+
+    if let Some(value) = env::var_os(LIB_PATH_ENV) {
+        let path = PathBuf::from(value);
+        if path.is_dir() {
+            paths.push(path.join(LIB_NAME));
+        } else {
+            paths.push(path);
+        }
+    }
+
+    if let Some(exe_dir) = env::current_exe()
+        .ok()
+        .and_then(|exe| exe.parent().map(PathBuf::from))
+    {
+        paths.push(exe_dir.join(LIB_NAME));
+    }
+
+    if let Ok(cwd) = env::current_dir() {
+        paths.push(cwd.join(LIB_NAME));
+    }
+
+    if let Some(dir) = BUNDLED_LIB_DIR {
+        paths.push(PathBuf::from(dir).join(LIB_NAME));
+    }
+
+    if let Some(search) = env::var_os(SEARCH_PATH_ENV) {
+        paths.extend(env::split_paths(&search).map(|dir| dir.join(LIB_NAME)));
+    }
+    if cfg!(unix) {
+        paths.push(PathBuf::from("/usr/local/lib").join(LIB_NAME));
+        paths.push(PathBuf::from("/usr/lib").join(LIB_NAME));
+    }
+
+    paths
+}
+
+/// Locates the native library.
+///
+/// Checks, in order: the `WEBSOCKET_LIB_PATH` environment variable, the
+/// directory of the running executable, the current working directory, the
+/// copy bundled by `build.rs` ([`BUNDLED_LIB_DIR`]), and the platform library
+/// search path. The error lists every path tried.
+pub fn find_library() -> Result<PathBuf, WsError> {
+    let tried = candidates();
+    match tried.iter().find(|path| path.is_file()) {
+        Some(path) => Ok(path.clone()),
+        None => Err(WsError::LibraryNotFound {
+            name: LIB_NAME,
+            tried,
+        }),
+    }
+}
@@ -0,0 +1,144 @@
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+
+/// Peer address reported by the native library in the `open` callback.
+///
+/// The host is kept as a string because the library may report a name
+/// rather than an IP; [`ip`](Self::ip) and
+/// [`socket_addr`](Self::socket_addr) give the parsed forms when possible.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct PeerAddr {
+    pub host: String,
+    pub port: Option<u16>,
+}
+
+impl PeerAddr {
+    /// Parses `host`, `host:port`, `[v6]` or `[v6]:port`.
+    ///
+    /// A bare IPv6 address without brackets is accepted as a host with no
+    /// port. Returns `None` for empty or malformed input.
+    pub fn parse(s: &str) -> Option<Self> {
+        let s = s.trim();
+        if s.is_empty() {
+            return None;
+        }
+
+        if let Some(rest) = s.strip_prefix('[') {
+            let (host, tail) = rest.split_once(']')?;
+            let port = match tail {
+                "" => None,
+                tail => Some(tail.strip_prefix(':')?.parse().ok()?),
+            };
+            return Some(PeerAddr {
+                host: host.to_owned(),
+                port,
+            });
+        }
+
+        if let Ok(addr) = s.parse::<SocketAddr>() {
+            return Some(addr.into());
+        }
+        if s.parse::<IpAddr>().is_ok() {
+            return Some(PeerAddr {
+                host: s.to_owned(),
+                port: None,
+            });
+        }
+
+        match s.rsplit_once(':') {
+            Some((host, port)) if !host.is_empty() && !host.contains(':') => Some(PeerAddr {
+                host: host.to_owned(),
+                port: Some(port.parse().ok()?),
+            }),
+            Some(_) => None,
+            None => Some(PeerAddr {
+                host: s.to_owned(),
+                port: None,
+            }),
+        }
+    }
+
+    pub fn ip(&self) -> Option<IpAddr> {
+        self.host.parse().ok()
+    }
+
+    /// The address as a `SocketAddr`, if the host is an IP and a port is known.
+    pub fn socket_addr(&self) -> Option<SocketAddr> {
+        Some(SocketAddr::new(self.ip()?, self.port?))
+    }
+}
+
+impl From<SocketAddr> for PeerAddr {
+    fn from(addr: SocketAddr) -> Self {
+        PeerAddr {
+            host: addr.ip().to_string(),
+            port: Some(addr.port()),
+        }
+    }
+}
+
+impl fmt::Display for PeerAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let bracket = self.host.contains(':');
+        match (bracket, self.port) {
+            (true, Some(port)) => write!(f, "[{}]:{port}", self.host),
+            (true, None) => write!(f, "[{}]", self.host),
+            (false, Some(port)) => write!(f, "{}:{port}", self.host),
+            (false, None) => f.write_str(&self.host),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn peer(host: &str, port: Option<u16>) -> Option<PeerAddr> {
+        Some(PeerAddr {
+            host: host.to_owned(),
+            port,
+        })
+    }
+
+    #[test]
+    fn ipv4_and_names() {
+        assert_eq!(
+            PeerAddr::parse("127.0.0.1:8080"),
+            peer("127.0.0.1", Some(8080))
+        );
+        assert_eq!(PeerAddr::parse(" 10.0.0.1 "), peer("10.0.0.1", None));
+        assert_eq!(
+            PeerAddr::parse("example.com:443"),
+            peer("example.com", Some(443))
+        );
+        assert_eq!(PeerAddr::parse("example.com"), peer("example.com", None));
+    }
+
+    #[test]
+    fn ipv6_with_and_without_brackets() {
+        assert_eq!(PeerAddr::parse("[::1]:9000"), peer("::1", Some(9000)));
+        assert_eq!(PeerAddr::parse("[::1]"), peer("::1", None));
+        assert_eq!(PeerAddr::parse("fe80::1"), peer("fe80::1", None));
+
+        let addr = PeerAddr::parse("[::1]:9000").unwrap();
+        assert_eq!(addr.socket_addr(), Some("[::1]:9000".parse().unwrap()));
+        assert_eq!(addr.to_string(), "[::1]:9000");
+    }
+
+    #[test]
+    fn malformed_input() {
+        for input in [
+            "",
+            "   ",
+            "host:port",
+            "host:70000",
+            ":80",
+            "[::1",
+            "[::1]9000",
+            "[::1]:x",
+            "a:b:c",
+        ] {
+            assert_eq!(PeerAddr::parse(input), None, "{input:?}");
+        }
+    }
+}
@@ -0,0 +1,34 @@
+use crate::{ClosureStatus, ConnectionId, ErrorKind, Opcode, PeerAddr};
+
+/// An event returned by [`WebSocket::operate_poll`](crate::WebSocket::operate_poll).
+///
+/// Payloads are owned copies, so events can be kept or moved to other
+/// threads.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PolledEvent {
+    /// A connection opened.
+    Open {
+        id: ConnectionId,
+        peer: Option<PeerAddr>,
+    },
+    /// A connection closed, as it would reach
+    /// [`on_close`](crate::WebSocket::on_close).
+    Close {
+        id: ConnectionId,
+        status: ClosureStatus,
+        reason: Option<String>,
+    },
+    /// A frame arrived, as it would reach [`on_frame`](crate::WebSocket::on_frame).
+    Frame {
+        id: ConnectionId,
+        opcode: Opcode,
+        data: Vec<u8>,
+    },
+    /// An error was reported, as it would reach
+    /// [`on_error_detailed`](crate::WebSocket::on_error_detailed).
+    Error {
+        id: Option<ConnectionId>,
+        kind: ErrorKind,
+        message: String,
+    },
+}
@@ -0,0 +1,82 @@
+use std::time::Instant;
+
+/// Token bucket allowing `rate` events per second, with bursts of up to
+/// one second's worth.
+pub(crate) struct TokenBucket {
+    rate: f64,
+    tokens: f64,
+    last: Instant,
+}
+
+impl TokenBucket {
+    /// A full bucket, as of `now`.
+    pub(crate) fn new(rate: u32, now: Instant) -> Self {
+        TokenBucket {
+            rate: rate.into(),
+            tokens: rate.into(),
+            last: now,
+        }
+    }
+
+    /// Refills the bucket for the time since the last call, then takes a
+    /// token if one is available.
+    pub(crate) fn try_take(&mut self, now: Instant) -> bool {
+        let refill = now.saturating_duration_since(self.last).as_secs_f64() * self.rate;
+        self.tokens = (self.tokens + refill).min(self.rate);
+        self.last = self.last.max(now);
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn take_all(bucket: &mut TokenBucket, now: Instant) -> usize {
+        (0..).take_while(|_| bucket.try_take(now)).count()
+    }
+
+    #[test]
+    fn starts_full_with_one_seconds_worth() {
+        let now = Instant::now();
+        let mut bucket = TokenBucket::new(5, now);
+        assert_eq!(take_all(&mut bucket, now), 5);
+        assert!(!bucket.try_take(now));
+    }
+
+    #[test]
+    fn refills_at_the_rate() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(10, start);
+        take_all(&mut bucket, start);
+
+        // A tenth of a second is worth one token at 10 per second.
+        assert!(!bucket.try_take(start + Duration::from_millis(50)));
+        assert!(bucket.try_take(start + Duration::from_millis(100)));
+        assert!(!bucket.try_take(start + Duration::from_millis(100)));
+        assert_eq!(take_all(&mut bucket, start + Duration::from_millis(400)), 3);
+    }
+
+    #[test]
+    fn refill_is_capped_at_the_burst() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(4, start);
+        take_all(&mut bucket, start);
+        assert_eq!(take_all(&mut bucket, start + Duration::from_secs(60)), 4);
+    }
+
+    #[test]
+    fn an_earlier_instant_refills_nothing() {
+        let start = Instant::now() + Duration::from_secs(1);
+        let mut bucket = TokenBucket::new(2, start);
+        take_all(&mut bucket, start);
+        assert!(!bucket.try_take(start - Duration::from_secs(1)));
+        assert!(bucket.try_take(start + Duration::from_millis(500)));
+    }
+}
@@ -0,0 +1,69 @@
+use crate::backoff::IdleBackoff;
+use crate::{OperateOutcome, WebSocket};
+
+/// Drives several [`WebSocket`] contexts from one thread.
+///
+/// Each [`turn`](Self::turn) calls `operate` once on every context, in the
+/// order they were added, so no context can starve another. A context whose
+/// `operate` reports it has nothing left to do is removed.
+#[derive(Default)]
+pub struct Reactor<'a> {
+    contexts: Vec<&'a WebSocket>,
+}
+
+impl<'a> Reactor<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds `ws` to the set of contexts driven by this reactor.
+    pub fn add(&mut self, ws: &'a WebSocket) {
+        self.contexts.push(ws);
+    }
+
+    /// Number of contexts still running.
+    pub fn len(&self) -> usize {
+        self.contexts.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.contexts.is_empty()
+    }
+
+    /// Runs one iteration: one `operate` call per context. Returns whether
+    /// any context did work.
+    pub fn turn(&mut self) -> bool {
+        let mut active = false;
+        self.contexts.retain(|ws| match ws.operate_once() {
+            OperateOutcome::Stopped => false,
+            OperateOutcome::Active => {
+                active = true;
+                true
+            }
+            OperateOutcome::Idle => true,
+        });
+        active
+    }
+
+    /// Runs until every context has stopped.
+    ///
+    /// Iterations where no context did work back off like
+    /// [`WebSocket::run`], capped by the smallest `idle_backoff_cap` among
+    /// the contexts.
+    pub fn run(&mut self) {
+        let cap = self
+            .contexts
+            .iter()
+            .map(|ws| ws.state.idle_backoff_cap.get())
+            .min()
+            .unwrap_or_default();
+        let mut backoff = IdleBackoff::new(cap);
+        while !self.is_empty() {
+            if self.turn() {
+                backoff.reset();
+            } else {
+                backoff.wait();
+            }
+        }
+    }
+}
@@ -0,0 +1,148 @@
+use crate::{ClosureStatus, Settings, WebSocket, WsError};
+use std::ffi::{CStr, CString};
+use std::thread;
+use std::time::Duration;
+
+type ConfigureFn = Box<dyn FnMut(&WebSocket) + Send>;
+type RetryFn = Box<dyn FnMut(u32, Duration) -> bool + Send>;
+
+/// Keeps a client connected, reopening with exponential backoff.
+///
+/// Each attempt creates a fresh [`WebSocket`], runs the `configure` hook so
+/// handlers can be registered again, applies the settings and opens the
+/// connection. A close with [`ClosureStatus::Normal`] ends [`run`](Self::run)
+/// without reconnecting; any other close, or a failed open, schedules
+/// another attempt. A failed setup does not: the settings are the same on
+/// every attempt, so its error is returned straight away.
+///
+/// The hooks are `Send`, so the whole helper can be moved to a thread of
+/// its own:
+///
+/// ```no_run
+/// use ws::{ClientReconnect, Endpoint, Settings};
+///
+/// let settings = Settings::builder()
+///     .endpoint(Endpoint::Client)
+///     .host("localhost:8080")
+///     .build()?;
+/// let mut client = ClientReconnect::new(settings, c"localhost", 8080)
+///     .configure(|ws| {
+///         ws.on_open(|conn, _| {
+///             let _ = conn.send_text("hello");
+///         })
+///     });
+/// let client = std::thread::spawn(move || client.run());
+/// # drop(client);
+/// # Ok::<(), ws::WsError>(())
+/// ```
+pub struct ClientReconnect {
+    settings: Settings,
+    host: CString,
+    port: u16,
+    base_delay: Duration,
+    max_delay: Duration,
+    max_attempts: Option<u32>,
+    configure: Option<ConfigureFn>,
+    on_retry: Option<RetryFn>,
+}
+
+impl ClientReconnect {
+    pub fn new(settings: Settings, host: &CStr, port: u16) -> Self {
+        ClientReconnect {
+            settings,
+            host: host.to_owned(),
+            port,
+            base_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(30),
+            max_attempts: None,
+            configure: None,
+            on_retry: None,
+        }
+    }
+
+    /// Delay before the first retry; doubled for each consecutive failure.
+    pub fn base_delay(mut self, delay: Duration) -> Self {
+        self.base_delay = delay;
+        self
+    }
+
+    /// Upper bound for the backoff delay.
+    pub fn max_delay(mut self, delay: Duration) -> Self {
+        self.max_delay = delay;
+        self
+    }
+
+    /// Consecutive failed attempts allowed before giving up. Unlimited by default.
+    pub fn max_attempts(mut self, attempts: u32) -> Self {
+        self.max_attempts = Some(attempts);
+        self
+    }
+
+    /// Called with every new context before setup, to register handlers.
+    pub fn configure(mut self, configure: impl FnMut(&WebSocket) + Send + 'static) -> Self {
+        self.configure = Some(Box::new(configure));
+        self
+    }
+
+    /// Called before each retry with the attempt number (starting at 1) and
+    /// the delay about to be slept. Returning `false` gives up.
+    pub fn on_retry(
+        mut self,
+        on_retry: impl FnMut(u32, Duration) -> bool + Send + 'static,
+    ) -> Self {
+        self.on_retry = Some(Box::new(on_retry));
+        self
+    }
+
+    fn delay_for(&self, attempt: u32) -> Duration {
+        let factor = 1u32
+            .checked_shl(attempt.saturating_sub(1))
+            .unwrap_or(u32::MAX);
+        self.base_delay.saturating_mul(factor).min(self.max_delay)
+    }
+
+    /// Runs until the connection closes cleanly, or returns
+    /// [`WsError::ReconnectGaveUp`] once retries are exhausted. Errors that
+    /// a retry cannot fix, such as the context failing to be created or set
+    /// up, are returned as they are.
+    pub fn run(&mut self) -> Result<(), WsError> {
+        let mut attempt = 0;
+        loop {
+            match self.connect_once()? {
+                Some(ClosureStatus::Normal) => return Ok(()),
+                // A connection was established, so the backoff starts over.
+                Some(_) => attempt = 1,
+                None => attempt += 1,
+            }
+
+            if self.max_attempts.is_some_and(|max| attempt > max) {
+                return Err(WsError::ReconnectGaveUp {
+                    attempts: attempt - 1,
+                });
+            }
+
+            let delay = self.delay_for(attempt);
+            if let Some(on_retry) = &mut self.on_retry
+                && !on_retry(attempt, delay)
+            {
+                return Err(WsError::ReconnectGaveUp { attempts: attempt });
+            }
+            thread::sleep(delay);
+        }
+    }
+
+    /// Runs one connection. Returns the close status if a connection was
+    /// established, `None` if open failed.
+    fn connect_once(&mut self) -> Result<Option<ClosureStatus>, WsError> {
+        let ws = WebSocket::new()?;
+        if let Some(configure) = &mut self.configure {
+            configure(&ws);
+        }
+        ws.setup(&self.settings)?;
+        if ws.open(&self.host, self.port).is_err() {
+            return Ok(None);
+        }
+        ws.run();
+        Ok(ws.last_close_status())
+    }
+}
@@ -0,0 +1,331 @@
+use crate::{ConnectionId, Frame, Sender, WsError};
+use std::cmp::{Ordering, Reverse};
+use std::collections::binary_heap::PeekMut;
+use std::collections::{BinaryHeap, HashMap};
+use std::future::Future;
+use std::hash::Hash;
+use std::pin::Pin;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock, Weak};
+use std::task::{Context, Poll, Waker};
+use std::thread;
+use std::time::{Duration, Instant};
+
+type Extract<K> = dyn Fn(&[u8]) -> Option<K> + Send + Sync;
+
+/// Where a response is handed from [`RequestMap::resolve`] to its
+/// [`Response`].
+#[derive(Default)]
+struct Slot {
+    response: Option<Vec<u8>>,
+    /// A later request took the same key.
+    replaced: bool,
+    waker: Option<Waker>,
+    /// The response's deadline has been handed to the timer thread.
+    timer: bool,
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Matches responses to requests sent over a connection, by a correlation
+/// id carried in the payload.
+///
+/// How the id is found in a payload is up to the application, so the map is
+/// built with a closure that extracts it. Feed every incoming frame or
+/// message to [`resolve`](Self::resolve) from the
+/// [`on_frame`](crate::WebSocket::on_frame) or
+/// [`on_message`](crate::WebSocket::on_message) handler, and await the
+/// [`Response`] that [`request`](Self::request) returns, on any executor:
+///
+/// ```no_run
+/// use std::time::Duration;
+/// use ws::{ConnectionId, Frame, Opcode, RequestMap, Sender, WsError};
+///
+/// // Payloads start with a 4-byte big-endian request id.
+/// fn request_id(data: &[u8]) -> Option<u32> {
+///     Some(u32::from_be_bytes(data.get(..4)?.try_into().ok()?))
+/// }
+///
+/// async fn call(
+///     requests: &RequestMap<u32>,
+///     sender: &Sender,
+///     id: ConnectionId,
+/// ) -> Result<Vec<u8>, WsError> {
+///     let mut frame = Frame::with_opcode(Opcode::Binary)?;
+///     frame.push(&7u32.to_be_bytes())?;
+///     requests
+///         .request(sender, id, 7, frame, Duration::from_secs(5))?
+///         .await
+/// }
+///
+/// # fn main() -> Result<(), WsError> {
+/// let requests = RequestMap::new(request_id);
+/// let ws = ws::WebSocket::new()?;
+/// let resolver = requests.clone();
+/// ws.on_message(move |_, _, data| {
+///     resolver.resolve(data);
+/// });
+/// # Ok(())
+/// # }
+/// ```
+///
+/// Nothing blocks: requesting from a handler on the event loop's thread is
+/// fine, as long as the [`Response`] is awaited elsewhere, since it can
+/// only complete once the loop runs again. Clones share the same pending
+/// requests.
+pub struct RequestMap<K> {
+    extract: Arc<Extract<K>>,
+    pending: Arc<Mutex<HashMap<K, Arc<Mutex<Slot>>>>>,
+}
+
+impl<K> Clone for RequestMap<K> {
+    fn clone(&self) -> Self {
+        RequestMap {
+            extract: Arc::clone(&self.extract),
+            pending: Arc::clone(&self.pending),
+        }
+    }
+}
+
+impl<K: Eq + Hash> RequestMap<K> {
+    /// Creates an empty map; `extract` returns the correlation id of a
+    /// payload, or `None` if it has none.
+    pub fn new(extract: impl Fn(&[u8]) -> Option<K> + Send + Sync + 'static) -> Self {
+        RequestMap {
+            extract: Arc::new(extract),
+            pending: Arc::default(),
+        }
+    }
+
+    /// Queues `frame` for connection `id` and returns a [`Response`] that
+    /// completes once a payload with correlation id `key` is
+    /// [`resolve`](Self::resolve)d or `timeout` elapses.
+    ///
+    /// Fails straight away if the frame cannot be queued, leaving any
+    /// earlier request with the same `key` pending. Otherwise a request
+    /// with a `key` that is still pending replaces the earlier one, which
+    /// then fails with [`WsError::RequestReplaced`].
+    pub fn request(
+        &self,
+        sender: &Sender,
+        id: ConnectionId,
+        key: K,
+        frame: Frame,
+        timeout: Duration,
+    ) -> Result<Response<K>, WsError>
+    where
+        K: Clone,
+    {
+        sender.send_batch(id, vec![frame])?;
+        let slot = Arc::new(Mutex::new(Slot::default()));
+        if let Some(earlier) = self.lock().insert(key.clone(), Arc::clone(&slot)) {
+            let mut earlier = lock(&earlier);
+            earlier.replaced = true;
+            if let Some(waker) = earlier.waker.take() {
+                waker.wake();
+            }
+        }
+        Ok(Response {
+            map: self.clone(),
+            key,
+            slot,
+            id,
+            timeout,
+            deadline: Instant::now() + timeout,
+        })
+    }
+
+    /// Hands `data` to the request waiting for its correlation id. Returns
+    /// whether one was waiting.
+    pub fn resolve(&self, data: &[u8]) -> bool {
+        let Some(key) = (self.extract)(data) else {
+            return false;
+        };
+        let Some(slot) = self.lock().remove(&key) else {
+            return false;
+        };
+        let mut slot = lock(&slot);
+        slot.response = Some(data.to_vec());
+        if let Some(waker) = slot.waker.take() {
+            waker.wake();
+        }
+        true
+    }
+
+    /// Number of requests waiting for a response.
+    pub fn pending(&self) -> usize {
+        self.lock().len()
+    }
+
+    /// Stops waiting on `key`, unless a later request has taken it over.
+    fn forget(&self, key: &K, slot: &Arc<Mutex<Slot>>) {
+        let mut pending = self.lock();
+        if pending.get(key).is_some_and(|held| Arc::ptr_eq(held, slot)) {
+            pending.remove(key);
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HashMap<K, Arc<Mutex<Slot>>>> {
+        lock(&self.pending)
+    }
+}
+
+/// The response to a [`RequestMap::request`], as a [`Future`].
+///
+/// Completes with the response payload, or fails with
+/// [`WsError::RequestTimeout`] once the request's timeout has elapsed. The
+/// first poll that has to wait hands the deadline to a timer thread shared
+/// by every response, so no timer from the executor is needed. Dropping it
+/// withdraws the request; a response arriving later is ignored.
+pub struct Response<K: Eq + Hash> {
+    map: RequestMap<K>,
+    key: K,
+    slot: Arc<Mutex<Slot>>,
+    id: ConnectionId,
+    timeout: Duration,
+    deadline: Instant,
+}
+
+impl<K: Eq + Hash> Future for Response<K> {
+    type Output = Result<Vec<u8>, WsError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut slot = lock(&self.slot);
+        if let Some(response) = slot.response.take() {
+            return Poll::Ready(Ok(response));
+        }
+        if slot.replaced {
+            return Poll::Ready(Err(WsError::RequestReplaced { id: self.id }));
+        }
+        if Instant::now() >= self.deadline {
+            drop(slot);
+            self.map.forget(&self.key, &self.slot);
+            return Poll::Ready(Err(WsError::RequestTimeout {
+                id: self.id,
+                timeout: self.timeout,
+            }));
+        }
+        slot.waker = Some(cx.waker().clone());
+        if !slot.timer {
+            slot.timer = true;
+            wake_at(self.deadline, Arc::downgrade(&self.slot));
+        }
+        Poll::Pending
+    }
+}
+
+impl<K: Eq + Hash> Drop for Response<K> {
+    fn drop(&mut self) {
+        self.map.forget(&self.key, &self.slot);
+    }
+}
+
+/// A response to wake once `at` has passed, unless it has been dropped.
+struct Deadline {
+    at: Instant,
+    slot: Weak<Mutex<Slot>>,
+}
+
+impl PartialEq for Deadline {
+    fn eq(&self, other: &Self) -> bool {
+        self.at == other.at
+    }
+}
+
+impl Eq for Deadline {}
+
+impl PartialOrd for Deadline {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Deadline {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.at.cmp(&other.at)
+    }
+}
+
+/// Has the timer thread wake `slot` at `at`, starting the thread on first
+/// use. One thread serves every pending response in the process.
+fn wake_at(at: Instant, slot: Weak<Mutex<Slot>>) {
+    static TIMER: OnceLock<mpsc::Sender<Deadline>> = OnceLock::new();
+    let timer = TIMER.get_or_init(|| {
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || run_timer(rx));
+        tx
+    });
+    let _ = timer.send(Deadline { at, slot });
+}
+
+/// Wakes each response once its deadline passes, earliest first.
+fn run_timer(rx: mpsc::Receiver<Deadline>) {
+    let mut deadlines: BinaryHeap<Reverse<Deadline>> = BinaryHeap::new();
+    loop {
+        let now = Instant::now();
+        while let Some(next) = deadlines.peek_mut()
+            && next.0.at <= now
+        {
+            let Reverse(due) = PeekMut::pop(next);
+            if let Some(slot) = due.slot.upgrade()
+                && let Some(waker) = lock(&slot).waker.take()
+            {
+                waker.wake();
+            }
+        }
+        let received = match deadlines.peek() {
+            Some(Reverse(next)) => rx.recv_timeout(next.at - now),
+            None => rx.recv().map_err(|_| RecvTimeoutError::Disconnected),
+        };
+        match received {
+            Ok(deadline) => deadlines.push(Reverse(deadline)),
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => return,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::task::Wake;
+
+    /// Sends its tag when woken.
+    struct Tagged(u8, Mutex<mpsc::Sender<u8>>);
+
+    impl Wake for Tagged {
+        fn wake(self: Arc<Self>) {
+            let _ = lock(&self.1).send(self.0);
+        }
+    }
+
+    fn slot(tag: u8, tx: &mpsc::Sender<u8>) -> Arc<Mutex<Slot>> {
+        let waker = Waker::from(Arc::new(Tagged(tag, Mutex::new(tx.clone()))));
+        Arc::new(Mutex::new(Slot {
+            waker: Some(waker),
+            ..Slot::default()
+        }))
+    }
+
+    #[test]
+    fn timer_wakes_live_slots_in_deadline_order() {
+        let (tx, rx) = mpsc::channel();
+        let now = Instant::now();
+        let late = slot(2, &tx);
+        let dropped = slot(3, &tx);
+        let early = slot(1, &tx);
+        wake_at(now + Duration::from_millis(40), Arc::downgrade(&late));
+        wake_at(now + Duration::from_millis(20), Arc::downgrade(&dropped));
+        wake_at(now + Duration::from_millis(10), Arc::downgrade(&early));
+        drop(dropped);
+
+        let woken: Vec<u8> = (0..2)
+            .map(|_| rx.recv_timeout(Duration::from_secs(5)).expect("woken"))
+            .collect();
+        assert_eq!(woken, [1, 2]);
+        assert!(now.elapsed() >= Duration::from_millis(40));
+        assert!(rx.recv_timeout(Duration::from_millis(50)).is_err());
+    }
+}
@@ -0,0 +1,99 @@
+//! Host resolution bounded by a timeout.
+
+use crate::WsError;
+use std::io;
+use std::net::{IpAddr, ToSocketAddrs};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+/// Resolves `host` within `timeout` and returns the address to connect to,
+/// the first one the resolver lists.
+///
+/// Resolution runs on a helper thread, since the standard resolver cannot
+/// be cancelled: a lookup that times out keeps its thread until the OS
+/// gives up on it. A host that resolves to nothing fails with
+/// [`WsError::Open`], as the native open would.
+pub(crate) fn within(host: &str, port: u16, timeout: Duration) -> Result<IpAddr, WsError> {
+    lookup_within(host, port, timeout, |host, port| {
+        (host, port)
+            .to_socket_addrs()
+            .map(|addrs| addrs.map(|addr| addr.ip()).collect())
+    })
+}
+
+fn lookup_within(
+    host: &str,
+    port: u16,
+    timeout: Duration,
+    lookup: impl FnOnce(&str, u16) -> io::Result<Vec<IpAddr>> + Send + 'static,
+) -> Result<IpAddr, WsError> {
+    let (tx, rx) = mpsc::channel();
+    let name = host.to_owned();
+    thread::spawn(move || {
+        let _ = tx.send(lookup(&name, port));
+    });
+    match rx.recv_timeout(timeout) {
+        Ok(Ok(addrs)) if !addrs.is_empty() => Ok(addrs[0]),
+        Ok(_) => Err(WsError::Open {
+            host: host.to_owned(),
+            port,
+        }),
+        Err(_) => Err(WsError::Timeout {
+            host: host.to_owned(),
+            timeout,
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    const TIMEOUT: Duration = Duration::from_millis(50);
+
+    #[test]
+    fn first_address_is_used() {
+        let addr = lookup_within("example", 80, TIMEOUT, |_, _| {
+            Ok(vec![
+                Ipv4Addr::new(10, 0, 0, 1).into(),
+                Ipv4Addr::LOCALHOST.into(),
+            ])
+        });
+        assert_eq!(addr.ok(), Some(Ipv4Addr::new(10, 0, 0, 1).into()));
+    }
+
+    #[test]
+    fn slow_lookup_times_out() {
+        let result = lookup_within("example", 80, TIMEOUT, |_, _| {
+            thread::sleep(Duration::from_secs(1));
+            Ok(vec![Ipv4Addr::LOCALHOST.into()])
+        });
+        assert!(matches!(
+            result,
+            Err(WsError::Timeout {
+                timeout: TIMEOUT,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn failed_or_empty_lookup_fails_the_open() {
+        let failed = lookup_within("example", 80, TIMEOUT, |_, _| {
+            Err(io::ErrorKind::NotFound.into())
+        });
+        assert!(matches!(failed, Err(WsError::Open { port: 80, .. })));
+        let empty = lookup_within("example", 80, TIMEOUT, |_, _| Ok(Vec::new()));
+        assert!(matches!(empty, Err(WsError::Open { port: 80, .. })));
+    }
+
+    #[test]
+    fn address_literals_resolve_to_themselves() {
+        assert_eq!(
+            within("127.0.0.1", 80, TIMEOUT).ok(),
+            Some(Ipv4Addr::LOCALHOST.into())
+        );
+    }
+}
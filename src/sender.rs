@@ -0,0 +1,95 @@
+use crate::{ConnectionId, Frame, WsError};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard, mpsc};
+
+/// Frames queued by a [`Sender`], emitted together by the next `operate`
+/// call.
+pub(crate) struct Outgoing {
+    pub(crate) id: ConnectionId,
+    pub(crate) frames: Vec<Frame>,
+}
+
+/// Bytes queued by [`Sender`]s and not yet handed to the native library.
+#[derive(Default)]
+pub(crate) struct Pending {
+    pub(crate) bytes: HashMap<ConnectionId, usize>,
+    /// Connections that had a send refused since the last flush, with the
+    /// bytes pending at the time.
+    pub(crate) refused: HashMap<ConnectionId, usize>,
+    /// `send_high_water_mark` of the applied settings.
+    pub(crate) high_water_mark: Option<usize>,
+}
+
+pub(crate) fn lock(pending: &Mutex<Pending>) -> MutexGuard<'_, Pending> {
+    pending.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Cloneable, thread-safe handle for sending frames from outside the
+/// operate loop.
+///
+/// Frames are queued and emitted at the start of the next
+/// [`WebSocket::operate`](crate::WebSocket::operate) call, before that
+/// iteration dispatches any inbound frames. Frames queued through one
+/// `Sender` go out in the order they were queued; frames from different
+/// clones are interleaved in the order they reached the queue. A frame
+/// queued from inside a callback is therefore emitted on the following
+/// iteration, after anything sent directly with `send_text`/`send_binary`.
+/// A [`send_batch`](Self::send_batch) is one queue entry, so frames from
+/// other clones never land between its frames.
+///
+/// With a [`send_high_water_mark`](crate::SettingsBuilder::send_high_water_mark)
+/// configured, a send that would push a connection's queued bytes past it is
+/// refused with [`WsError::Backpressure`] and nothing is queued.
+#[derive(Clone)]
+pub struct Sender {
+    pub(crate) tx: mpsc::Sender<Outgoing>,
+    pub(crate) pending: Arc<Mutex<Pending>>,
+}
+
+impl Sender {
+    /// Queues `text` as a text frame for `id`.
+    pub fn send_text(&self, id: ConnectionId, text: impl Into<String>) -> Result<(), WsError> {
+        self.queue(id, vec![Frame::text(&text.into())?])
+    }
+
+    /// Queues `data` as a binary frame for `id`.
+    pub fn send_binary(&self, id: ConnectionId, data: impl Into<Vec<u8>>) -> Result<(), WsError> {
+        self.queue(id, vec![Frame::binary(&data.into())?])
+    }
+
+    /// Queues `frames` for `id` as one unit, emitted back to back.
+    ///
+    /// See [`WebSocket::send_batch`](crate::WebSocket::send_batch) for what
+    /// happens when one of them fails.
+    pub fn send_batch(&self, id: ConnectionId, frames: Vec<Frame>) -> Result<(), WsError> {
+        self.queue(id, frames)
+    }
+
+    /// Bytes queued for `id` that the event loop has not emitted yet.
+    pub fn pending_bytes(&self, id: ConnectionId) -> usize {
+        lock(&self.pending).bytes.get(&id).copied().unwrap_or(0)
+    }
+
+    fn queue(&self, id: ConnectionId, frames: Vec<Frame>) -> Result<(), WsError> {
+        let size: usize = frames.iter().map(Frame::len).sum();
+        let mut pending = lock(&self.pending);
+        let queued = pending.bytes.get(&id).copied().unwrap_or(0);
+        // An empty queue always accepts, so a single large send cannot be
+        // refused forever.
+        if let Some(mark) = pending.high_water_mark
+            && queued > 0
+            && queued + size > mark
+        {
+            pending.refused.insert(id, queued);
+            return Err(WsError::Backpressure {
+                id,
+                pending: queued,
+            });
+        }
+        self.tx
+            .send(Outgoing { id, frames })
+            .map_err(|_| WsError::ContextGone)?;
+        *pending.bytes.entry(id).or_default() += size;
+        Ok(())
+    }
+}
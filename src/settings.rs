@@ -0,0 +1,1060 @@
+use crate::WsError;
+use crate::sys::*;
+use std::ffi::{CString, c_char, c_int};
+use std::fs;
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+use std::ptr;
+use std::time::Duration;
+
+/// Valid LZ77 window sizes for `permessage-deflate` (RFC 7692, section 7.1.2).
+pub const WINDOW_BITS_RANGE: RangeInclusive<u8> = 8..=15;
+
+/// Which side of the connection a context plays.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Endpoint {
+    Client,
+    Server,
+}
+
+impl Endpoint {
+    fn raw(self) -> e_ws_endpoint_type {
+        match self {
+            Endpoint::Client => e_ws_endpoint_type_endpoint_client,
+            Endpoint::Server => e_ws_endpoint_type_endpoint_server,
+        }
+    }
+}
+
+/// What happens to a message larger than the
+/// [`message_limit`](SettingsBuilder::message_limit).
+///
+/// Whatever the action, the
+/// [`on_limit_exceeded`](crate::WebSocket::on_limit_exceeded) handler is
+/// called first.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum LimitAction {
+    /// Drop the message and close the connection with
+    /// [`ClosureStatus::MessageTooBig`](crate::ClosureStatus::MessageTooBig) (1009).
+    #[default]
+    Close,
+    /// Drop the message and keep the connection open.
+    Ignore,
+    /// Deliver the first `message_limit` bytes, flagged through
+    /// [`Connection::is_truncated`](crate::Connection::is_truncated), and
+    /// drop the rest. Text is cut back to a character boundary.
+    Truncate,
+}
+
+/// Transport mode, mirroring the native `e_ws_mode`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Mode {
+    /// Plain TCP (`ws://`).
+    Unsecured,
+    /// TLS (`wss://`); a server needs a certificate and key.
+    Secured,
+    /// A value the crate does not know about, passed to the native library
+    /// unchanged; kept so newer native modes stay usable.
+    Unknown(i32),
+}
+
+impl Mode {
+    pub fn from_raw(raw: e_ws_mode) -> Self {
+        if raw == e_ws_mode_mode_unsecured {
+            Mode::Unsecured
+        } else if raw == e_ws_mode_mode_secured {
+            Mode::Secured
+        } else {
+            Mode::Unknown(raw as c_int)
+        }
+    }
+
+    fn raw(self) -> e_ws_mode {
+        match self {
+            Mode::Unsecured => e_ws_mode_mode_unsecured,
+            Mode::Secured => e_ws_mode_mode_secured,
+            Mode::Unknown(other) => other as e_ws_mode,
+        }
+    }
+}
+
+/// Chainable builder for [`Settings`].
+///
+/// Unset values fall back to the same defaults the example binaries use.
+#[derive(Debug, Clone)]
+pub struct SettingsBuilder {
+    endpoint: Endpoint,
+    ping_interval: u32,
+    ping_timeout: u32,
+    message_limit: usize,
+    limit_action: LimitAction,
+    idle_timeout: Option<u32>,
+    idle_backoff_cap: u32,
+    close_timeout: u32,
+    strict_utf8: bool,
+    strict_protocol: bool,
+    #[cfg(feature = "frame-trace")]
+    frame_trace: bool,
+    send_high_water_mark: Option<usize>,
+    connect_timeout: Option<u32>,
+    handshake_timeout: Option<u32>,
+    max_connections: Option<usize>,
+    max_frames_per_sec: Option<u32>,
+    auto_mask_frame: Option<bool>,
+    permessage_deflate: bool,
+    client_max_window_bits: u8,
+    server_max_window_bits: u8,
+    host: Option<String>,
+    allowed_origin: Option<String>,
+    mode: Option<Mode>,
+    tls_ca_cert: Option<PathBuf>,
+    tls_cert: Option<PathBuf>,
+    tls_key: Option<PathBuf>,
+}
+
+impl Default for SettingsBuilder {
+    fn default() -> Self {
+        SettingsBuilder {
+            endpoint: Endpoint::Client,
+            ping_interval: 60_000,
+            ping_timeout: 30_000,
+            message_limit: 4 * 1024 * 1024,
+            limit_action: LimitAction::Close,
+            idle_timeout: None,
+            idle_backoff_cap: 5,
+            close_timeout: 5_000,
+            strict_utf8: false,
+            strict_protocol: true,
+            #[cfg(feature = "frame-trace")]
+            frame_trace: false,
+            send_high_water_mark: None,
+            connect_timeout: None,
+            handshake_timeout: None,
+            max_connections: None,
+            max_frames_per_sec: None,
+            auto_mask_frame: None,
+            permessage_deflate: false,
+            client_max_window_bits: 15,
+            server_max_window_bits: 15,
+            host: None,
+            allowed_origin: None,
+            mode: None,
+            tls_ca_cert: None,
+            tls_cert: None,
+            tls_key: None,
+        }
+    }
+}
+
+impl SettingsBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn endpoint(mut self, endpoint: Endpoint) -> Self {
+        self.endpoint = endpoint;
+        self
+    }
+
+    /// Interval between keepalive pings, in milliseconds.
+    pub fn ping_interval(mut self, ms: u32) -> Self {
+        self.ping_interval = ms;
+        self
+    }
+
+    /// Time to wait for a pong before the peer is considered dead, in milliseconds.
+    pub fn ping_timeout(mut self, ms: u32) -> Self {
+        self.ping_timeout = ms;
+        self
+    }
+
+    /// Maximum size of a single message, in bytes.
+    ///
+    /// The limit is passed to the native library, which parses frame headers
+    /// and allocates receive buffers; it is the only place a declared frame
+    /// length can be refused before memory is reserved for it, and the C
+    /// settings have no separate per-frame cap. The wrapper checks each
+    /// delivered frame against the limit again; see
+    /// [`WebSocket::on_limit_exceeded`](crate::WebSocket::on_limit_exceeded).
+    pub fn message_limit(mut self, bytes: usize) -> Self {
+        self.message_limit = bytes;
+        self
+    }
+
+    /// What to do with a message over the `message_limit`. Defaults to
+    /// [`LimitAction::Close`].
+    ///
+    /// Only applies to frames the native library delivers; one it refuses
+    /// against the limit itself never reaches the wrapper.
+    pub fn limit_action(mut self, action: LimitAction) -> Self {
+        self.limit_action = action;
+        self
+    }
+
+    /// Closes a connection with [`ClosureStatus::PolicyViolation`] once no
+    /// data frame has arrived on it for `ms` milliseconds. Disabled by default.
+    ///
+    /// Only text, binary and continuation frames reset the timer, so
+    /// keepalive pings and pongs do not keep a silent peer connected. Use
+    /// this alongside [`ping_timeout`](Self::ping_timeout), which catches
+    /// peers that stop responding altogether.
+    ///
+    /// [`ClosureStatus::PolicyViolation`]: crate::ClosureStatus::PolicyViolation
+    pub fn idle_timeout(mut self, ms: u32) -> Self {
+        self.idle_timeout = Some(ms);
+        self
+    }
+
+    /// Longest sleep between idle iterations of [`WebSocket::run`], in
+    /// milliseconds. Defaults to 5.
+    ///
+    /// Lower values reduce the latency of the first event after a quiet
+    /// period at the cost of more wakeups; 0 never sleeps and spins a core.
+    ///
+    /// [`WebSocket::run`]: crate::WebSocket::run
+    pub fn idle_backoff_cap(mut self, ms: u32) -> Self {
+        self.idle_backoff_cap = ms;
+        self
+    }
+
+    /// How long to wait, in milliseconds, for the peer to answer a close
+    /// frame this side sent. Defaults to 5000.
+    ///
+    /// Past that, the connection is given up on: the close handler is called
+    /// with [`ClosureStatus::Abnormal`] and nothing the native library
+    /// reports about it afterwards reaches a handler. The C API has no call
+    /// to drop a connection, so its socket stays open until the native
+    /// library's own ping timeout or the peer ends it.
+    ///
+    /// [`ClosureStatus::Abnormal`]: crate::ClosureStatus::Abnormal
+    pub fn close_timeout(mut self, ms: u32) -> Self {
+        self.close_timeout = ms;
+        self
+    }
+
+    /// Rejects text with invalid UTF-8 instead of delivering it. Off by
+    /// default.
+    ///
+    /// When enabled, an invalid text frame or reassembled text message is
+    /// reported to the error handler as [`WsError::InvalidUtf8`] and the
+    /// connection is closed with [`ClosureStatus::InvalidPayload`] (1007), as
+    /// RFC 6455 requires. A frame that merely ends partway through a
+    /// character is let through, since a continuation may complete it.
+    ///
+    /// [`ClosureStatus::InvalidPayload`]: crate::ClosureStatus::InvalidPayload
+    pub fn strict_utf8(mut self, enabled: bool) -> Self {
+        self.strict_utf8 = enabled;
+        self
+    }
+
+    /// Fails connections that send a frame with a reserved opcode (3-7 or
+    /// 11-15). On by default.
+    ///
+    /// Such a frame is reported to the error handler as
+    /// [`WsError::ReservedOpcode`] and the connection is closed with
+    /// [`ClosureStatus::ProtocolError`] (1002), as RFC 6455 requires. When
+    /// disabled, the frame is passed to the frame handler as
+    /// [`Opcode::Unknown`](crate::Opcode::Unknown), for peers using a private
+    /// extension.
+    ///
+    /// RSV bits are not covered: the native frame callback passes only the
+    /// opcode and payload, so the wrapper never sees them. Rejecting a frame
+    /// with an unnegotiated RSV bit is left to the native library's frame
+    /// parser.
+    ///
+    /// [`ClosureStatus::ProtocolError`]: crate::ClosureStatus::ProtocolError
+    pub fn strict_protocol(mut self, enabled: bool) -> Self {
+        self.strict_protocol = enabled;
+        self
+    }
+
+    /// Logs the payload of every frame received or sent, as hex, through
+    /// the `log` crate at trace level (target `ws`). Off by default; only
+    /// available with the `frame-trace` feature, so it costs nothing in
+    /// builds without it.
+    ///
+    /// Each line carries the connection, direction, opcode and payload
+    /// length. The native API does not expose frame headers, so FIN, RSV
+    /// bits, the masking key and the on-the-wire length cannot be logged,
+    /// and with `permessage-deflate` the payload is shown uncompressed.
+    #[cfg(feature = "frame-trace")]
+    pub fn frame_trace(mut self, enabled: bool) -> Self {
+        self.frame_trace = enabled;
+        self
+    }
+
+    /// Caps the bytes a connection may have queued through
+    /// [`Sender`](crate::Sender)s before further sends are refused with
+    /// [`WsError::Backpressure`]. Unlimited by default.
+    ///
+    /// The queue is emptied by every `operate` call, so this bounds how much
+    /// producer threads can queue between iterations; it does not see data
+    /// the native library itself has buffered.
+    ///
+    /// There is no send timeout to go with it: the C API has no write
+    /// deadline and the sockets belong to the native library, so an emit to
+    /// a stalled peer can be neither bounded nor interrupted from Rust. A
+    /// peer that stops reading shows up here instead, once its
+    /// [`Sender`](crate::Sender) sends are refused, and can then be closed.
+    pub fn send_high_water_mark(mut self, bytes: usize) -> Self {
+        self.send_high_water_mark = Some(bytes);
+        self
+    }
+
+    /// Longest, in milliseconds, a client [`open`](crate::WebSocket::open)
+    /// may spend resolving the host name. Unlimited by default.
+    ///
+    /// The native `websocket_open` offers no timeout, so with this set the
+    /// host is resolved from Rust first: a lookup still running when it
+    /// expires fails the open with [`WsError::Timeout`], and a host that
+    /// does not resolve with [`WsError::Open`]. The native library is then
+    /// handed the first address found instead of the name, so it does not
+    /// resolve again; the [`host`](Self::host) setting reaches it
+    /// unchanged. The TCP connect and the upgrade are the native
+    /// library's and cannot be bounded, only reported on once they take
+    /// too long, with [`handshake_timeout`](Self::handshake_timeout).
+    pub fn connect_timeout(mut self, ms: u32) -> Self {
+        self.connect_timeout = Some(ms);
+        self
+    }
+
+    /// Longest, in milliseconds, a client connection may take from
+    /// [`open`](crate::WebSocket::open) returning to the server completing
+    /// the upgrade. Unlimited by default.
+    ///
+    /// A handshake still pending when it expires is reported to the error
+    /// handler as [`WsError::HandshakeTimeout`] and no longer waited for.
+    /// The attempt itself cannot be cancelled, since `websocket_open` does
+    /// not hand out the socket: the native library keeps it until the
+    /// server gives up. Should the upgrade complete after all, it is closed
+    /// with [`ClosureStatus::GoingAway`](crate::ClosureStatus::GoingAway)
+    /// without reaching the open handler, provided it can be told apart:
+    /// the peer's address and port must equal the host and port passed to
+    /// `open`, as they do for an open by address or with a
+    /// [`connect_timeout`](Self::connect_timeout), which resolves the name
+    /// first. Otherwise upgrades are matched to opens by port alone, and a
+    /// late one opens as usual.
+    pub fn handshake_timeout(mut self, ms: u32) -> Self {
+        self.handshake_timeout = Some(ms);
+        self
+    }
+
+    /// Most connections a context keeps open at once. Unlimited by default.
+    ///
+    /// A connection opening past the limit is reported to the error handler
+    /// as [`WsError::TooManyConnections`] and closed straight away with
+    /// [`ClosureStatus::PolicyViolation`]; the open and close handlers never
+    /// see it. The C API has no listen backlog setting, so the native
+    /// library still accepts it at the socket level first.
+    ///
+    /// [`ClosureStatus::PolicyViolation`]: crate::ClosureStatus::PolicyViolation
+    pub fn max_connections(mut self, max: usize) -> Self {
+        self.max_connections = Some(max);
+        self
+    }
+
+    /// Most frames per second a single connection may send, of any opcode.
+    /// Unlimited by default.
+    ///
+    /// Each connection gets a token bucket that refills at this rate and
+    /// holds up to one second's worth, so short bursts are tolerated. A
+    /// frame beyond the budget is dropped, reported to the error handler as
+    /// [`WsError::RateLimited`] and the connection is closed with
+    /// [`ClosureStatus::PolicyViolation`].
+    ///
+    /// [`ClosureStatus::PolicyViolation`]: crate::ClosureStatus::PolicyViolation
+    pub fn max_frames_per_sec(mut self, max: u32) -> Self {
+        self.max_frames_per_sec = Some(max);
+        self
+    }
+
+    /// Overrides frame masking. Defaults to `true` for clients and `false` for servers.
+    ///
+    /// Servers must not mask (RFC 6455, section 5.1) and a compliant client
+    /// closes the connection on a masked frame, so [`build`](Self::build)
+    /// rejects `true` for a server.
+    ///
+    /// Masking keys are drawn by the native library, and the C API has no
+    /// way to supply one, so masked output cannot be made deterministic from
+    /// Rust. Byte-exact tests should compare unmasked frames instead.
+    pub fn auto_mask_frame(mut self, enabled: bool) -> Self {
+        self.auto_mask_frame = Some(enabled);
+        self
+    }
+
+    /// Negotiates `permessage-deflate` (RFC 7692). Off by default.
+    ///
+    /// Once negotiated, the native library compresses every outgoing data
+    /// message on the connection. There is no per-message opt-out: a frame
+    /// is created from its opcode alone and the C API exposes neither the
+    /// RSV1 bit nor a compression flag, so already-compressed payloads are
+    /// deflated again. Keep the extension off for connections that mostly
+    /// carry such data.
+    ///
+    /// Only the requested parameters are known on the Rust side. The native
+    /// library does not report whether a connection actually negotiated the
+    /// extension or with which window bits.
+    pub fn permessage_deflate(mut self, enabled: bool) -> Self {
+        self.permessage_deflate = enabled;
+        self
+    }
+
+    /// Sets both the client and server maximum window bits.
+    pub fn window_bits(mut self, bits: u8) -> Self {
+        self.client_max_window_bits = bits;
+        self.server_max_window_bits = bits;
+        self
+    }
+
+    /// The client's maximum window bits, 8 to 15.
+    ///
+    /// The native settings carry a single window size, so only this side's
+    /// value reaches the library: a client applies
+    /// `client_max_window_bits`, a server
+    /// [`server_max_window_bits`](Self::server_max_window_bits). The other
+    /// value is validated and kept on [`Settings`] but not negotiated.
+    pub fn client_max_window_bits(mut self, bits: u8) -> Self {
+        self.client_max_window_bits = bits;
+        self
+    }
+
+    /// The server's maximum window bits, 8 to 15. Only applied on a server;
+    /// see [`client_max_window_bits`](Self::client_max_window_bits).
+    pub fn server_max_window_bits(mut self, bits: u8) -> Self {
+        self.server_max_window_bits = bits;
+        self
+    }
+
+    /// Host sent to the native library, as `host`, `host:port` or
+    /// `[ipv6]:port`.
+    ///
+    /// Clients may also pass a `ws://` or `wss://` URL; the scheme selects
+    /// [`mode`](Self::mode) and the path is not sent to the native
+    /// library but kept as [`Settings::path`]. The value is validated by
+    /// [`build`](Self::build).
+    pub fn host(mut self, host: impl Into<String>) -> Self {
+        self.host = Some(host.into());
+        self
+    }
+
+    /// Origin a server accepts connections from, e.g. `https://example.com`.
+    ///
+    /// The native library rejects handshakes whose `Origin` header does not
+    /// match, and [`on_open`](crate::WebSocket::on_open) is never called for
+    /// them. The C API reports no event for a rejected origin, so blocked
+    /// attempts cannot be observed, or logged, from Rust. Unset, connections
+    /// from any origin are accepted.
+    pub fn allowed_origin(mut self, origin: impl Into<String>) -> Self {
+        self.allowed_origin = Some(origin.into());
+        self
+    }
+
+    /// Transport mode. Defaults to the URL scheme given to
+    /// [`host`](Self::host), or [`Mode::Unsecured`].
+    pub fn mode(mut self, mode: Mode) -> Self {
+        self.mode = Some(mode);
+        self
+    }
+
+    /// Runs the connection over TLS: shorthand for [`mode`](Self::mode) with
+    /// [`Mode::Secured`] or [`Mode::Unsecured`].
+    pub fn secure(self, enabled: bool) -> Self {
+        self.mode(if enabled {
+            Mode::Secured
+        } else {
+            Mode::Unsecured
+        })
+    }
+
+    /// PEM file with the CA certificates used to verify the peer.
+    pub fn tls_ca_cert_path(mut self, path: impl AsRef<Path>) -> Self {
+        self.tls_ca_cert = Some(path.as_ref().to_owned());
+        self
+    }
+
+    /// PEM file with this side's certificate. Required for a secure server.
+    pub fn tls_cert_path(mut self, path: impl AsRef<Path>) -> Self {
+        self.tls_cert = Some(path.as_ref().to_owned());
+        self
+    }
+
+    /// PEM file with the private key for [`tls_cert_path`](Self::tls_cert_path).
+    pub fn tls_key_path(mut self, path: impl AsRef<Path>) -> Self {
+        self.tls_key = Some(path.as_ref().to_owned());
+        self
+    }
+
+    /// Validates the configuration and produces [`Settings`].
+    ///
+    /// Fails with [`WsError::InvalidSetting`] when a window bits value is
+    /// outside [`WINDOW_BITS_RANGE`], the host is malformed, a URL scheme
+    /// contradicts [`mode`](Self::mode) or a secure server lacks a
+    /// certificate or key, a ping interval or timeout is zero or the timeout
+    /// is not shorter than the interval, and with [`WsError::TlsFile`] when
+    /// a TLS file cannot be read.
+    pub fn build(self) -> Result<Settings, WsError> {
+        for (name, bits) in [
+            ("client_max_window_bits", self.client_max_window_bits),
+            ("server_max_window_bits", self.server_max_window_bits),
+        ] {
+            if !WINDOW_BITS_RANGE.contains(&bits) {
+                return Err(WsError::InvalidSetting {
+                    name,
+                    reason: format!(
+                        "{bits} is outside the RFC 7692 range {}..={}",
+                        WINDOW_BITS_RANGE.start(),
+                        WINDOW_BITS_RANGE.end()
+                    ),
+                });
+            }
+        }
+
+        check_ping(self.ping_interval, self.ping_timeout)?;
+
+        if self.endpoint == Endpoint::Server && self.auto_mask_frame == Some(true) {
+            return Err(WsError::InvalidSetting {
+                name: "auto_mask_frame",
+                reason: "a server must not mask its frames".to_owned(),
+            });
+        }
+
+        let mut host = self
+            .host
+            .as_deref()
+            .map(|host| parse_host(host, self.endpoint))
+            .transpose()?;
+        let scheme = host.as_ref().and_then(|h| h.secure);
+        let mode = match (self.mode, scheme) {
+            (Some(explicit), Some(scheme)) if (explicit == Mode::Secured) != scheme => {
+                return Err(WsError::InvalidSetting {
+                    name: "mode",
+                    reason: format!(
+                        "{explicit:?} contradicts the {} URL scheme",
+                        if scheme { "wss" } else { "ws" }
+                    ),
+                });
+            }
+            (Some(explicit), _) => explicit,
+            (None, Some(true)) => Mode::Secured,
+            (None, _) => Mode::Unsecured,
+        };
+
+        if mode == Mode::Secured && self.endpoint == Endpoint::Server {
+            for (name, path) in [
+                ("tls_cert_path", &self.tls_cert),
+                ("tls_key_path", &self.tls_key),
+            ] {
+                if path.is_none() {
+                    return Err(WsError::InvalidSetting {
+                        name,
+                        reason: "a secure server needs a certificate and key".to_owned(),
+                    });
+                }
+            }
+        }
+
+        // Read every file before allocating anything for the native struct.
+        let tls_ca_cert = read_pem(self.tls_ca_cert.as_deref())?;
+        let tls_cert = read_pem(self.tls_cert.as_deref())?;
+        let tls_key = read_pem(self.tls_key.as_deref())?;
+
+        // The native struct has a single window size: the one used by this
+        // side's compressor.
+        let window_bits = match self.endpoint {
+            Endpoint::Client => self.client_max_window_bits,
+            Endpoint::Server => self.server_max_window_bits,
+        };
+
+        let port = host.as_ref().and_then(|h| h.port);
+        let path = host.as_mut().and_then(|h| h.path.take());
+        let host = host.map(|h| CString::new(h.authority)).transpose()?;
+        let allowed_origin = self.allowed_origin.map(CString::new).transpose()?;
+
+        let mut raw: ws_settings_t = unsafe { core::mem::zeroed() };
+        raw.endpoint = self.endpoint.raw();
+        raw.mode = mode.raw();
+        raw.ping_interval = self.ping_interval as _;
+        raw.ping_timeout = self.ping_timeout as _;
+        raw.message_limit = self.message_limit as _;
+        raw.auto_mask_frame = self
+            .auto_mask_frame
+            .unwrap_or(self.endpoint == Endpoint::Client);
+        raw.extensions.permessage_deflate.enabled = self.permessage_deflate;
+        raw.extensions.permessage_deflate.window_bits = window_bits as _;
+
+        Ok(Settings {
+            raw,
+            endpoint: self.endpoint,
+            host,
+            port,
+            path,
+            allowed_origin,
+            tls_ca_cert,
+            tls_cert,
+            tls_key,
+            message_limit: self.message_limit,
+            limit_action: self.limit_action,
+            idle_timeout: self.idle_timeout.map(|ms| Duration::from_millis(ms.into())),
+            idle_backoff_cap: Duration::from_millis(self.idle_backoff_cap.into()),
+            close_timeout: Duration::from_millis(self.close_timeout.into()),
+            strict_utf8: self.strict_utf8,
+            strict_protocol: self.strict_protocol,
+            #[cfg(feature = "frame-trace")]
+            frame_trace: self.frame_trace,
+            send_high_water_mark: self.send_high_water_mark,
+            connect_timeout: self
+                .connect_timeout
+                .map(|ms| Duration::from_millis(ms.into())),
+            handshake_timeout: self
+                .handshake_timeout
+                .map(|ms| Duration::from_millis(ms.into())),
+            max_connections: self.max_connections,
+            max_frames_per_sec: self.max_frames_per_sec,
+            client_max_window_bits: self.client_max_window_bits,
+            server_max_window_bits: self.server_max_window_bits,
+        })
+    }
+}
+
+impl TryFrom<&str> for Settings {
+    type Error = WsError;
+
+    /// Same as [`Settings::from_url`].
+    fn try_from(url: &str) -> Result<Self, Self::Error> {
+        Settings::from_url(url)
+    }
+}
+
+/// A validated [`SettingsBuilder::host`] value.
+struct HostSpec {
+    /// `host[:port]` with any scheme and path removed.
+    authority: String,
+    /// The explicit port, or the scheme's default for a URL.
+    port: Option<u16>,
+    /// Whether the URL scheme was `wss`; `None` without a scheme.
+    secure: Option<bool>,
+    /// Path and query of a URL, `/` if it had none; `None` without a scheme.
+    path: Option<String>,
+}
+
+fn parse_host(input: &str, endpoint: Endpoint) -> Result<HostSpec, WsError> {
+    let invalid = |reason: &str| WsError::InvalidSetting {
+        name: "host",
+        reason: format!("{input:?}: {reason}"),
+    };
+    if input.contains('\0') {
+        return Err(invalid("contains a nul byte"));
+    }
+
+    let (secure, rest) = match input.split_once("://") {
+        None => (None, input),
+        Some(_) if endpoint == Endpoint::Server => {
+            return Err(invalid("URLs are only accepted for clients"));
+        }
+        Some((scheme, rest)) => match scheme.to_ascii_lowercase().as_str() {
+            "ws" => (Some(false), rest),
+            "wss" => (Some(true), rest),
+            _ => return Err(invalid("the scheme must be ws or wss")),
+        },
+    };
+    let authority = rest.split(['/', '?', '#']).next().unwrap_or_default();
+    let path = secure.map(|_| {
+        let resource = rest[authority.len()..]
+            .split('#')
+            .next()
+            .unwrap_or_default();
+        if resource.starts_with('/') {
+            resource.to_owned()
+        } else {
+            format!("/{resource}")
+        }
+    });
+
+    let (name, port) = if let Some(bracketed) = authority.strip_prefix('[') {
+        let (ip, after) = bracketed
+            .split_once(']')
+            .ok_or_else(|| invalid("missing closing ']'"))?;
+        let port = match after {
+            "" => None,
+            after => Some(
+                after
+                    .strip_prefix(':')
+                    .ok_or_else(|| invalid("expected ':' after ']'"))?,
+            ),
+        };
+        (ip, port)
+    } else {
+        match authority.split_once(':') {
+            Some((_, port)) if port.contains(':') => {
+                return Err(invalid("IPv6 addresses must be enclosed in brackets"));
+            }
+            Some((name, port)) => (name, Some(port)),
+            None => (authority, None),
+        }
+    };
+    if name.is_empty() {
+        return Err(invalid("missing host name"));
+    }
+    let port = port
+        .map(|port| match port.parse::<u16>() {
+            Ok(0) | Err(_) => Err(invalid("the port must be a number from 1 to 65535")),
+            Ok(port) => Ok(port),
+        })
+        .transpose()?
+        .or(secure.map(|secure| if secure { 443 } else { 80 }));
+
+    Ok(HostSpec {
+        authority: authority.to_owned(),
+        port,
+        secure,
+        path,
+    })
+}
+
+/// Checks that pings are sent at all and that a missing pong is detected
+/// before the next ping is due.
+fn check_ping(interval: u32, timeout: u32) -> Result<(), WsError> {
+    let invalid = |name, reason: String| Err(WsError::InvalidSetting { name, reason });
+    if interval == 0 {
+        return invalid("ping_interval", "must be at least 1 ms".to_owned());
+    }
+    if timeout == 0 {
+        return invalid("ping_timeout", "must be at least 1 ms".to_owned());
+    }
+    if timeout >= interval {
+        return invalid(
+            "ping_timeout",
+            format!(
+                "{timeout} ms is not shorter than ping_interval ({interval} ms); \
+                 the next ping would be sent before the previous one timed out"
+            ),
+        );
+    }
+    Ok(())
+}
+
+/// Reads a PEM file into a C string, if a path was given.
+fn read_pem(path: Option<&Path>) -> Result<Option<CString>, WsError> {
+    let Some(path) = path else {
+        return Ok(None);
+    };
+    let pem = fs::read(path).map_err(|source| WsError::TlsFile {
+        path: path.to_owned(),
+        source,
+    })?;
+    Ok(Some(CString::new(pem)?))
+}
+
+/// Settings ready to be passed to [`WebSocket::setup`](crate::WebSocket::setup).
+///
+/// Strings are kept as owned `CString`s and freed by Rust on drop. The
+/// native struct stored here has no string pointers set; they are filled in
+/// on a copy only for the duration of a [`with_raw`](Self::with_raw) call,
+/// so nothing allocated by Rust is ever reachable for the native library to
+/// free with `libc::free`.
+pub struct Settings {
+    /// Every field but the string pointers, which stay null.
+    raw: ws_settings_t,
+    endpoint: Endpoint,
+    host: Option<CString>,
+    port: Option<u16>,
+    path: Option<String>,
+    allowed_origin: Option<CString>,
+    tls_ca_cert: Option<CString>,
+    tls_cert: Option<CString>,
+    tls_key: Option<CString>,
+    message_limit: usize,
+    limit_action: LimitAction,
+    idle_timeout: Option<Duration>,
+    idle_backoff_cap: Duration,
+    close_timeout: Duration,
+    strict_utf8: bool,
+    strict_protocol: bool,
+    #[cfg(feature = "frame-trace")]
+    frame_trace: bool,
+    send_high_water_mark: Option<usize>,
+    connect_timeout: Option<Duration>,
+    handshake_timeout: Option<Duration>,
+    max_connections: Option<usize>,
+    max_frames_per_sec: Option<u32>,
+    client_max_window_bits: u8,
+    server_max_window_bits: u8,
+}
+
+// SAFETY: `raw` holds no pointers (see `with_raw`); everything else is
+// owned data.
+unsafe impl Send for Settings {}
+unsafe impl Sync for Settings {}
+
+impl Settings {
+    pub fn builder() -> SettingsBuilder {
+        SettingsBuilder::new()
+    }
+
+    /// The builder defaults for `endpoint`, with no host: masking on for a
+    /// client and off for a server (RFC 6455, section 5.1), plain TCP, and
+    /// the example binaries' ping and size limits.
+    ///
+    /// A server can be set up with these directly; a client still needs a
+    /// host, through [`builder`](Self::builder) or [`from_url`](Self::from_url).
+    pub fn default_for(endpoint: Endpoint) -> Settings {
+        SettingsBuilder::new()
+            .endpoint(endpoint)
+            .build()
+            .expect("the builder defaults are valid")
+    }
+
+    /// Client settings for a `ws://` or `wss://` URL, with every other value
+    /// at its default.
+    ///
+    /// The scheme selects the [`mode`](SettingsBuilder::mode), and the
+    /// host, [`port`](Self::port) and [`path`](Self::path) are split out for
+    /// the [`open`](crate::WebSocket::open) call. Fails with
+    /// [`WsError::InvalidSetting`] for anything but a ws or wss URL.
+    pub fn from_url(url: &str) -> Result<Settings, WsError> {
+        if !url.contains("://") {
+            return Err(WsError::InvalidSetting {
+                name: "host",
+                reason: format!("{url:?}: expected a ws:// or wss:// URL"),
+            });
+        }
+        SettingsBuilder::new()
+            .endpoint(Endpoint::Client)
+            .host(url)
+            .build()
+    }
+
+    /// Calls `f` with the native settings struct, built on the stack with
+    /// its string pointers borrowing from `self`.
+    ///
+    /// The pointers are only valid until `f` returns; neither `f` nor the
+    /// native library may keep or free them. `websocket_setup` copies what
+    /// it needs, so [`WebSocket::setup`](crate::WebSocket::setup) passes the
+    /// struct this way.
+    pub fn with_raw<R>(&self, f: impl FnOnce(&ws_settings_t) -> R) -> R {
+        fn ptr(s: &Option<CString>) -> *mut c_char {
+            s.as_ref()
+                .map_or(ptr::null_mut(), |s| s.as_ptr().cast_mut())
+        }
+        let mut raw = self.raw;
+        raw.host = ptr(&self.host) as _;
+        raw.allowed_origin = ptr(&self.allowed_origin) as _;
+        raw.ssl_ca_cert = ptr(&self.tls_ca_cert) as _;
+        raw.ssl_own_cert = ptr(&self.tls_cert) as _;
+        raw.ssl_private_key = ptr(&self.tls_key) as _;
+        f(&raw)
+    }
+
+    /// Whether outgoing frames are masked.
+    pub fn auto_mask_frame(&self) -> bool {
+        self.raw.auto_mask_frame
+    }
+
+    /// Keepalive ping interval, in milliseconds.
+    pub fn ping_interval(&self) -> u32 {
+        self.raw.ping_interval as _
+    }
+
+    /// Time to wait for a pong, in milliseconds.
+    pub fn ping_timeout(&self) -> u32 {
+        self.raw.ping_timeout as _
+    }
+
+    /// Changes the keepalive ping interval, in milliseconds. Takes effect on
+    /// the next [`WebSocket::setup`](crate::WebSocket::setup); see
+    /// [`WebSocket::set_ping_interval`](crate::WebSocket::set_ping_interval).
+    ///
+    /// Fails with [`WsError::InvalidSetting`], leaving the interval
+    /// unchanged, under the same rules as [`SettingsBuilder::build`].
+    pub fn set_ping_interval(&mut self, ms: u32) -> Result<(), WsError> {
+        check_ping(ms, self.ping_timeout())?;
+        self.raw.ping_interval = ms as _;
+        Ok(())
+    }
+
+    pub fn message_limit(&self) -> usize {
+        self.message_limit
+    }
+
+    pub fn limit_action(&self) -> LimitAction {
+        self.limit_action
+    }
+
+    pub fn idle_timeout(&self) -> Option<Duration> {
+        self.idle_timeout
+    }
+
+    pub fn idle_backoff_cap(&self) -> Duration {
+        self.idle_backoff_cap
+    }
+
+    pub fn close_timeout(&self) -> Duration {
+        self.close_timeout
+    }
+
+    pub fn strict_utf8(&self) -> bool {
+        self.strict_utf8
+    }
+
+    pub fn strict_protocol(&self) -> bool {
+        self.strict_protocol
+    }
+
+    #[cfg(feature = "frame-trace")]
+    pub fn frame_trace(&self) -> bool {
+        self.frame_trace
+    }
+
+    pub fn send_high_water_mark(&self) -> Option<usize> {
+        self.send_high_water_mark
+    }
+
+    pub fn connect_timeout(&self) -> Option<Duration> {
+        self.connect_timeout
+    }
+
+    pub fn handshake_timeout(&self) -> Option<Duration> {
+        self.handshake_timeout
+    }
+
+    pub fn max_connections(&self) -> Option<usize> {
+        self.max_connections
+    }
+
+    pub fn mode(&self) -> Mode {
+        Mode::from_raw(self.raw.mode)
+    }
+
+    pub fn max_frames_per_sec(&self) -> Option<u32> {
+        self.max_frames_per_sec
+    }
+
+    pub fn endpoint(&self) -> Endpoint {
+        self.endpoint
+    }
+
+    pub fn is_secure(&self) -> bool {
+        self.mode() == Mode::Secured
+    }
+
+    pub fn host(&self) -> Option<&str> {
+        self.host.as_deref().and_then(|h| h.to_str().ok())
+    }
+
+    /// Port given in [`SettingsBuilder::host`], if any. For a URL without
+    /// one, the scheme's default: 443 for `wss`, 80 for `ws`.
+    pub fn port(&self) -> Option<u16> {
+        self.port
+    }
+
+    /// Path and query of a URL given in [`SettingsBuilder::host`], e.g.
+    /// `/chat?room=1`; `/` if the URL had none.
+    pub fn path(&self) -> Option<&str> {
+        self.path.as_deref()
+    }
+
+    pub fn allowed_origin(&self) -> Option<&str> {
+        self.allowed_origin.as_deref().and_then(|o| o.to_str().ok())
+    }
+
+    /// As configured; only passed to the native library on a client.
+    pub fn client_max_window_bits(&self) -> u8 {
+        self.client_max_window_bits
+    }
+
+    /// As configured; only passed to the native library on a server.
+    pub fn server_max_window_bits(&self) -> u8 {
+        self.server_max_window_bits
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn host(input: &str) -> HostSpec {
+        parse_host(input, Endpoint::Client).unwrap()
+    }
+
+    fn rejects(input: &str, endpoint: Endpoint) -> bool {
+        parse_host(input, endpoint).is_err()
+    }
+
+    #[test]
+    fn plain_host_and_port() {
+        let spec = host("localhost:4433");
+        assert_eq!(spec.authority, "localhost:4433");
+        assert_eq!(spec.port, Some(4433));
+        assert_eq!(spec.secure, None);
+        assert_eq!(spec.path, None);
+
+        let spec = host("localhost");
+        assert_eq!(spec.port, None);
+    }
+
+    #[test]
+    fn ipv6_needs_brackets() {
+        let spec = host("[::1]:9000");
+        assert_eq!(spec.authority, "[::1]:9000");
+        assert_eq!(spec.port, Some(9000));
+        assert_eq!(host("[fe80::1]").port, None);
+        assert_eq!(host("wss://[::1]/x").port, Some(443));
+
+        assert!(rejects("::1", Endpoint::Client));
+        assert!(rejects("fe80::1:9000", Endpoint::Client));
+        assert!(rejects("[::1", Endpoint::Client));
+        assert!(rejects("[::1]9000", Endpoint::Client));
+        assert!(rejects("[]:9000", Endpoint::Client));
+    }
+
+    #[test]
+    fn url_schemes_default_their_port() {
+        let spec = host("ws://example.com");
+        assert_eq!(spec.authority, "example.com");
+        assert_eq!(spec.port, Some(80));
+        assert_eq!(spec.secure, Some(false));
+        assert_eq!(spec.path.as_deref(), Some("/"));
+
+        let spec = host("WSS://example.com/chat?room=1#top");
+        assert_eq!(spec.port, Some(443));
+        assert_eq!(spec.secure, Some(true));
+        assert_eq!(spec.path.as_deref(), Some("/chat?room=1"));
+
+        let spec = host("wss://example.com:8443?x=1");
+        assert_eq!(spec.authority, "example.com:8443");
+        assert_eq!(spec.port, Some(8443));
+        assert_eq!(spec.path.as_deref(), Some("/?x=1"));
+    }
+
+    #[test]
+    fn bad_schemes_and_ports_are_rejected() {
+        assert!(rejects("http://example.com", Endpoint::Client));
+        assert!(rejects("://example.com", Endpoint::Client));
+        assert!(rejects("ws://example.com", Endpoint::Server));
+        assert!(rejects("ws://:80", Endpoint::Client));
+        assert!(rejects("example.com:", Endpoint::Client));
+        assert!(rejects("example.com:0", Endpoint::Client));
+        assert!(rejects("example.com:70000", Endpoint::Client));
+        assert!(rejects("example.com:-1", Endpoint::Client));
+        assert!(rejects("exa\0mple.com", Endpoint::Client));
+    }
+
+    fn ping_error(interval: u32, timeout: u32) -> Option<&'static str> {
+        match check_ping(interval, timeout) {
+            Ok(()) => None,
+            Err(WsError::InvalidSetting { name, .. }) => Some(name),
+            Err(e) => panic!("unexpected error: {e}"),
+        }
+    }
+
+    #[test]
+    fn ping_timeout_must_fall_inside_the_interval() {
+        assert_eq!(ping_error(2, 1), None);
+        assert_eq!(ping_error(u32::MAX, u32::MAX - 1), None);
+        assert_eq!(ping_error(1, 1), Some("ping_timeout"));
+        assert_eq!(ping_error(1, 2), Some("ping_timeout"));
+        assert_eq!(ping_error(0, 0), Some("ping_interval"));
+        assert_eq!(ping_error(0, 1), Some("ping_interval"));
+        assert_eq!(ping_error(1, 0), Some("ping_timeout"));
+    }
+}
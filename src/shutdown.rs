@@ -0,0 +1,34 @@
+use crate::WsError;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Set by the SIGINT handler; polled by the operate loop.
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Installs the process-wide Ctrl+C handler on first call.
+///
+/// The first Ctrl+C only raises the flag so the loop can exit and run
+/// destruction normally; a second one while the flag is still set
+/// force-exits the process.
+pub(crate) fn install_ctrl_c_handler() -> Result<(), WsError> {
+    static INSTALLED: AtomicBool = AtomicBool::new(false);
+    if INSTALLED.swap(true, Ordering::SeqCst) {
+        return Ok(());
+    }
+    ctrlc::set_handler(|| {
+        if INTERRUPTED.swap(true, Ordering::SeqCst) {
+            std::process::exit(130);
+        }
+    })
+    .map_err(|e| {
+        INSTALLED.store(false, Ordering::SeqCst);
+        WsError::Signal(e)
+    })
+}
+
+pub(crate) fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+pub(crate) fn reset() {
+    INTERRUPTED.store(false, Ordering::SeqCst);
+}
@@ -0,0 +1,89 @@
+//! Socket operations on the fds reported by the native library.
+
+use std::ffi::c_int;
+use std::io;
+
+/// A boolean socket option, as `(level, name)`.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Flag {
+    pub(crate) label: &'static str,
+    #[cfg(unix)]
+    level: c_int,
+    #[cfg(unix)]
+    name: c_int,
+}
+
+#[cfg(unix)]
+pub(crate) const NODELAY: Flag = Flag {
+    label: "TCP_NODELAY",
+    level: libc::IPPROTO_TCP,
+    name: libc::TCP_NODELAY,
+};
+
+#[cfg(unix)]
+pub(crate) const KEEPALIVE: Flag = Flag {
+    label: "SO_KEEPALIVE",
+    level: libc::SOL_SOCKET,
+    name: libc::SO_KEEPALIVE,
+};
+
+#[cfg(not(unix))]
+pub(crate) const NODELAY: Flag = Flag {
+    label: "TCP_NODELAY",
+};
+
+#[cfg(not(unix))]
+pub(crate) const KEEPALIVE: Flag = Flag {
+    label: "SO_KEEPALIVE",
+};
+
+#[cfg(unix)]
+pub(crate) fn set(fd: c_int, flag: Flag, enabled: bool) -> io::Result<()> {
+    let value = c_int::from(enabled);
+    let ret = unsafe {
+        libc::setsockopt(
+            fd,
+            flag.level,
+            flag.name,
+            (&value as *const c_int).cast(),
+            size_of::<c_int>() as libc::socklen_t,
+        )
+    };
+    if ret == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+#[cfg(unix)]
+pub(crate) fn get(fd: c_int, flag: Flag) -> io::Result<bool> {
+    let mut value: c_int = 0;
+    let mut len = size_of::<c_int>() as libc::socklen_t;
+    let ret = unsafe {
+        libc::getsockopt(
+            fd,
+            flag.level,
+            flag.name,
+            (&mut value as *mut c_int).cast(),
+            &mut len,
+        )
+    };
+    if ret == 0 {
+        Ok(value != 0)
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+// The native library reports Windows sockets truncated to a `c_int`, which
+// is not a handle `setsockopt` can be trusted with.
+#[cfg(not(unix))]
+pub(crate) fn set(_fd: c_int, _flag: Flag, _enabled: bool) -> io::Result<()> {
+    Err(io::ErrorKind::Unsupported.into())
+}
+
+#[cfg(not(unix))]
+pub(crate) fn get(_fd: c_int, _flag: Flag) -> io::Result<bool> {
+    Err(io::ErrorKind::Unsupported.into())
+}
@@ -0,0 +1,104 @@
+use crate::Opcode;
+use futures_core::Stream;
+use std::collections::VecDeque;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::task::{Context, Poll, Waker};
+
+#[derive(Default)]
+struct Shared {
+    frames: VecDeque<(Opcode, Vec<u8>)>,
+    closed: bool,
+    waker: Option<Waker>,
+}
+
+fn lock(shared: &Mutex<Shared>) -> MutexGuard<'_, Shared> {
+    shared.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// The frames received on one connection, as an asynchronous
+/// [`Stream`], from [`Connection::frames`](crate::Connection::frames).
+///
+/// Each item is the opcode and an owned copy of the payload, as
+/// [`on_frame`](crate::WebSocket::on_frame) sees them; the handlers keep
+/// running alongside. The stream ends once the connection has closed and
+/// every frame received before has been yielded:
+///
+/// ```no_run
+/// # async fn handle(mut frames: ws::FrameStream) {
+/// use futures_core::Stream;
+/// use std::future::poll_fn;
+/// use std::pin::Pin;
+///
+/// while let Some((opcode, data)) = poll_fn(|cx| Pin::new(&mut frames).poll_next(cx)).await {
+///     println!("{opcode:?}: {} bytes", data.len());
+/// }
+/// # }
+/// ```
+///
+/// (`StreamExt::next` from `futures` does the same.) The event loop still
+/// has to run, typically on its own thread through
+/// [`WebSocket::spawn`](crate::WebSocket::spawn); the stream is `Send` and
+/// can be moved to any executor. Frames are buffered without bound until
+/// polled, and no longer once the stream is dropped.
+pub struct FrameStream {
+    shared: Arc<Mutex<Shared>>,
+}
+
+/// The event loop's end of a [`FrameStream`].
+pub(crate) struct FrameSink {
+    shared: Arc<Mutex<Shared>>,
+}
+
+pub(crate) fn channel() -> (FrameSink, FrameStream) {
+    let shared = Arc::new(Mutex::new(Shared::default()));
+    (
+        FrameSink {
+            shared: Arc::clone(&shared),
+        },
+        FrameStream { shared },
+    )
+}
+
+impl FrameSink {
+    /// Queues a frame. Returns `false` if the stream was dropped, in which
+    /// case the sink can be discarded.
+    pub(crate) fn push(&self, opcode: Opcode, data: &[u8]) -> bool {
+        if Arc::strong_count(&self.shared) == 1 {
+            return false;
+        }
+        let mut shared = lock(&self.shared);
+        shared.frames.push_back((opcode, data.to_vec()));
+        if let Some(waker) = shared.waker.take() {
+            waker.wake();
+        }
+        true
+    }
+}
+
+impl Drop for FrameSink {
+    /// Ends the stream once the queued frames are consumed.
+    fn drop(&mut self) {
+        let mut shared = lock(&self.shared);
+        shared.closed = true;
+        if let Some(waker) = shared.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+impl Stream for FrameStream {
+    type Item = (Opcode, Vec<u8>);
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let mut shared = lock(&self.shared);
+        if let Some(frame) = shared.frames.pop_front() {
+            return Poll::Ready(Some(frame));
+        }
+        if shared.closed {
+            return Poll::Ready(None);
+        }
+        shared.waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
@@ -0,0 +1,290 @@
+use crate::sys::*;
+use std::ffi::{CStr, c_int};
+use std::fmt;
+
+/// Native event a raw callback can be registered for with
+/// [`WebSocket::on_event`](crate::WebSocket::on_event).
+///
+/// Each variant documents the callback signature the native library
+/// expects; every callback receives the context pointer first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Event {
+    /// `fn(ctx, fd: c_int, addr: *const c_char)`
+    Open,
+    /// `fn(ctx, fd: c_int, status: e_ws_closure_status)`
+    Close,
+    /// `fn(ctx, fd: c_int, opcode: e_ws_frame_opcode, data: *const c_uchar, len: usize)`
+    Frame,
+    /// `fn(ctx, msg: *const c_char)`
+    Error,
+}
+
+impl Event {
+    /// The name passed to the native `websocket_on`.
+    pub fn name(self) -> &'static CStr {
+        match self {
+            Event::Open => c"open",
+            Event::Close => c"close",
+            Event::Frame => c"frame",
+            Event::Error => c"error",
+        }
+    }
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.name().to_string_lossy())
+    }
+}
+
+/// Frame opcode as defined by RFC 6455, section 5.2.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Opcode {
+    Continuation,
+    Text,
+    Binary,
+    Close,
+    Ping,
+    Pong,
+    /// A value the crate does not know about; kept so nothing is dropped.
+    /// Received frames only carry one when
+    /// [`strict_protocol`](crate::SettingsBuilder::strict_protocol) is off.
+    Unknown(i32),
+}
+
+impl Opcode {
+    pub fn is_control(self) -> bool {
+        matches!(self, Opcode::Close | Opcode::Ping | Opcode::Pong)
+    }
+
+    /// Decodes the opcode handed to a raw `frame` callback.
+    pub fn from_raw(raw: e_ws_frame_opcode) -> Self {
+        OPCODES
+            .iter()
+            .find(|&&(value, _)| value == raw)
+            .map_or(Opcode::Unknown(raw as i32), |&(_, opcode)| opcode)
+    }
+
+    pub(crate) fn to_raw(self) -> e_ws_frame_opcode {
+        match self {
+            Opcode::Unknown(other) => other as e_ws_frame_opcode,
+            known => OPCODES
+                .iter()
+                .find(|&&(_, opcode)| opcode == known)
+                .map(|&(value, _)| value)
+                .expect("every known opcode is in OPCODES"),
+        }
+    }
+}
+
+/// The native value of each known opcode, as the header defines it.
+const OPCODES: [(e_ws_frame_opcode, Opcode); 6] = [
+    (e_ws_frame_opcode_opcode_continuation, Opcode::Continuation),
+    (e_ws_frame_opcode_opcode_text, Opcode::Text),
+    (e_ws_frame_opcode_opcode_binary, Opcode::Binary),
+    (e_ws_frame_opcode_opcode_close, Opcode::Close),
+    (e_ws_frame_opcode_opcode_ping, Opcode::Ping),
+    (e_ws_frame_opcode_opcode_pong, Opcode::Pong),
+];
+
+impl From<c_int> for Opcode {
+    fn from(raw: c_int) -> Self {
+        Opcode::from_raw(raw as e_ws_frame_opcode)
+    }
+}
+
+impl From<Opcode> for c_int {
+    fn from(opcode: Opcode) -> Self {
+        opcode.to_raw() as c_int
+    }
+}
+
+/// Close status code as defined by RFC 6455, section 7.4.1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ClosureStatus {
+    Normal,
+    GoingAway,
+    ProtocolError,
+    UnsupportedData,
+    NoStatus,
+    Abnormal,
+    InvalidPayload,
+    PolicyViolation,
+    MessageTooBig,
+    MandatoryExtension,
+    InternalError,
+    TlsHandshake,
+    /// A code the crate does not know about, including application codes,
+    /// as the native library or the close frame carried it.
+    Unknown(i32),
+}
+
+impl ClosureStatus {
+    /// Decodes the status handed to a raw `close` callback.
+    pub fn from_raw(raw: e_ws_closure_status) -> Self {
+        CLOSURE_STATUSES
+            .iter()
+            .find(|&&(value, _)| value == raw)
+            .map_or(ClosureStatus::Unknown(raw as i32), |&(_, status)| status)
+    }
+
+    pub(crate) fn to_raw(self) -> e_ws_closure_status {
+        match self {
+            ClosureStatus::Unknown(other) => other as e_ws_closure_status,
+            known => CLOSURE_STATUSES
+                .iter()
+                .find(|&&(_, status)| status == known)
+                .map(|&(value, _)| value)
+                .expect("every known status is in CLOSURE_STATUSES"),
+        }
+    }
+
+    /// Decodes the status code carried in a close frame's payload.
+    pub fn from_code(code: u16) -> Self {
+        match code {
+            1000 => ClosureStatus::Normal,
+            1001 => ClosureStatus::GoingAway,
+            1002 => ClosureStatus::ProtocolError,
+            1003 => ClosureStatus::UnsupportedData,
+            1005 => ClosureStatus::NoStatus,
+            1006 => ClosureStatus::Abnormal,
+            1007 => ClosureStatus::InvalidPayload,
+            1008 => ClosureStatus::PolicyViolation,
+            1009 => ClosureStatus::MessageTooBig,
+            1010 => ClosureStatus::MandatoryExtension,
+            1011 => ClosureStatus::InternalError,
+            1015 => ClosureStatus::TlsHandshake,
+            other => ClosureStatus::Unknown(other.into()),
+        }
+    }
+
+    /// The status code sent in a close frame's payload.
+    pub fn code(self) -> u16 {
+        match self {
+            ClosureStatus::Normal => 1000,
+            ClosureStatus::GoingAway => 1001,
+            ClosureStatus::ProtocolError => 1002,
+            ClosureStatus::UnsupportedData => 1003,
+            ClosureStatus::NoStatus => 1005,
+            ClosureStatus::Abnormal => 1006,
+            ClosureStatus::InvalidPayload => 1007,
+            ClosureStatus::PolicyViolation => 1008,
+            ClosureStatus::MessageTooBig => 1009,
+            ClosureStatus::MandatoryExtension => 1010,
+            ClosureStatus::InternalError => 1011,
+            ClosureStatus::TlsHandshake => 1015,
+            ClosureStatus::Unknown(other) => other as u16,
+        }
+    }
+
+    /// Whether this status may appear in a close frame. 1005, 1006 and 1015
+    /// are reserved for reporting locally and must never be sent; of the
+    /// codes without a variant, only those for libraries, frameworks and
+    /// applications, 3000 to 4999, may be (RFC 6455, section 7.4).
+    pub fn is_sendable(self) -> bool {
+        match self {
+            ClosureStatus::NoStatus | ClosureStatus::Abnormal | ClosureStatus::TlsHandshake => {
+                false
+            }
+            ClosureStatus::Unknown(other) => (3000..=4999).contains(&other),
+            _ => true,
+        }
+    }
+}
+
+impl From<c_int> for ClosureStatus {
+    fn from(raw: c_int) -> Self {
+        ClosureStatus::from_raw(raw as e_ws_closure_status)
+    }
+}
+
+impl From<ClosureStatus> for c_int {
+    fn from(status: ClosureStatus) -> Self {
+        status.to_raw() as c_int
+    }
+}
+
+/// The native value of each known status, as the header defines it. These
+/// need not be the codes a close frame carries; see [`ClosureStatus::code`].
+const CLOSURE_STATUSES: [(e_ws_closure_status, ClosureStatus); 12] = [
+    (e_ws_closure_status_closure_normal, ClosureStatus::Normal),
+    (
+        e_ws_closure_status_closure_going_away,
+        ClosureStatus::GoingAway,
+    ),
+    (
+        e_ws_closure_status_closure_protocol_error,
+        ClosureStatus::ProtocolError,
+    ),
+    (
+        e_ws_closure_status_closure_unsupported_data,
+        ClosureStatus::UnsupportedData,
+    ),
+    (
+        e_ws_closure_status_closure_no_status,
+        ClosureStatus::NoStatus,
+    ),
+    (
+        e_ws_closure_status_closure_abnormal,
+        ClosureStatus::Abnormal,
+    ),
+    (
+        e_ws_closure_status_closure_invalid_payload,
+        ClosureStatus::InvalidPayload,
+    ),
+    (
+        e_ws_closure_status_closure_policy_violation,
+        ClosureStatus::PolicyViolation,
+    ),
+    (
+        e_ws_closure_status_closure_message_too_big,
+        ClosureStatus::MessageTooBig,
+    ),
+    (
+        e_ws_closure_status_closure_mandatory_extension,
+        ClosureStatus::MandatoryExtension,
+    ),
+    (
+        e_ws_closure_status_closure_internal_error,
+        ClosureStatus::InternalError,
+    ),
+    (
+        e_ws_closure_status_closure_tls_handshake,
+        ClosureStatus::TlsHandshake,
+    ),
+];
+
+/// Result status returned by the native API.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum WsStatus {
+    Ok,
+    Error,
+}
+
+impl WsStatus {
+    pub(crate) fn from_raw(raw: e_ws_status) -> Self {
+        WsStatus::from(raw as c_int)
+    }
+}
+
+impl From<c_int> for WsStatus {
+    fn from(raw: c_int) -> Self {
+        if raw == e_ws_status_status_error as c_int {
+            WsStatus::Error
+        } else {
+            WsStatus::Ok
+        }
+    }
+}
+
+/// What a call to [`WebSocket::operate_with_timeout`](crate::WebSocket::operate_with_timeout)
+/// or [`WebSocket::operate_n`](crate::WebSocket::operate_n) did.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OperateOutcome {
+    /// A callback fired or queued frames were sent.
+    Active,
+    /// Nothing happened. `operate_with_timeout` sleeps before returning it.
+    Idle,
+    /// The context has nothing left to do.
+    Stopped,
+}
@@ -0,0 +1,106 @@
+use std::fmt;
+
+/// Version of the native library these bindings were generated against,
+/// as read by `build.rs` from the submodule's `CMakeLists.txt`.
+pub const BINDINGS_VERSION: Option<&str> = option_env!("WEBSOCKET_NATIVE_VERSION");
+
+/// A `major.minor.patch` version of the native library.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Version {
+    pub major: u32,
+    pub minor: u32,
+    pub patch: u32,
+}
+
+impl Version {
+    /// Parses `major[.minor[.patch]]`, ignoring anything after a `-` or `+`.
+    /// Missing parts are zero.
+    pub fn parse(s: &str) -> Option<Self> {
+        let core = s.trim().split(['-', '+']).next()?;
+        let mut parts = core.split('.').map(str::parse::<u32>);
+        let major = parts.next()?.ok()?;
+        let minor = parts.next().transpose().ok()?.unwrap_or(0);
+        let patch = parts.next().transpose().ok()?.unwrap_or(0);
+        if parts.next().is_some() {
+            return None;
+        }
+        Some(Version {
+            major,
+            minor,
+            patch,
+        })
+    }
+
+    /// [`BINDINGS_VERSION`], parsed.
+    pub fn bindings() -> Option<Self> {
+        BINDINGS_VERSION.and_then(Version::parse)
+    }
+
+    /// Whether a library of this version can be used through bindings
+    /// generated for `expected`: the major versions must match.
+    pub fn is_compatible_with(self, expected: Version) -> bool {
+        self.major == expected.major
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn version(major: u32, minor: u32, patch: u32) -> Option<Version> {
+        Some(Version {
+            major,
+            minor,
+            patch,
+        })
+    }
+
+    #[test]
+    fn full_and_partial_versions() {
+        assert_eq!(Version::parse("1.2.3"), version(1, 2, 3));
+        assert_eq!(Version::parse(" 1.2.3\n"), version(1, 2, 3));
+        assert_eq!(Version::parse("1.2"), version(1, 2, 0));
+        assert_eq!(Version::parse("4"), version(4, 0, 0));
+    }
+
+    #[test]
+    fn suffixes_are_ignored() {
+        assert_eq!(Version::parse("1.2.3-beta.1"), version(1, 2, 3));
+        assert_eq!(Version::parse("1.2.3+build.7"), version(1, 2, 3));
+        assert_eq!(Version::parse("2.0-rc1+abc"), version(2, 0, 0));
+    }
+
+    #[test]
+    fn malformed_versions() {
+        for input in ["", "v1.2.3", "1.2.3.4", "1..3", "1.x", "-1.0", "1.2.3 beta"] {
+            assert_eq!(Version::parse(input), None, "{input:?}");
+        }
+    }
+
+    #[test]
+    fn compatibility_follows_the_major_version() {
+        let expected = Version::parse("1.4.0").unwrap();
+        assert!(
+            Version::parse("1.0.9")
+                .unwrap()
+                .is_compatible_with(expected)
+        );
+        assert!(
+            Version::parse("1.9.0")
+                .unwrap()
+                .is_compatible_with(expected)
+        );
+        assert!(
+            !Version::parse("2.0.0")
+                .unwrap()
+                .is_compatible_with(expected)
+        );
+        assert_eq!(expected.to_string(), "1.4.0");
+    }
+}
@@ -0,0 +1,45 @@
+//! Ownership of registered handlers.
+//!
+//! Needs the native library to be loadable (see the README's search order).
+
+use std::sync::Arc;
+use ws::WebSocket;
+
+#[test]
+fn handlers_are_dropped_with_the_context() {
+    let captured = Arc::new(());
+    let ws = WebSocket::new().expect("context");
+    for _ in 0..2 {
+        let open = Arc::clone(&captured);
+        ws.on_open(move |_, _| {
+            let _ = &open;
+        });
+    }
+    let close = Arc::clone(&captured);
+    ws.on_close(move |_, _, _| {
+        let _ = &close;
+    });
+    // The replaced open handler is already gone.
+    assert_eq!(Arc::strong_count(&captured), 3);
+
+    drop(ws);
+    assert_eq!(Arc::strong_count(&captured), 1);
+}
+
+#[test]
+fn clear_handlers_drops_them_immediately() {
+    let captured = Arc::new(());
+    let ws = WebSocket::new().expect("context");
+    let frame = Arc::clone(&captured);
+    ws.on_frame(move |_, _, _| {
+        let _ = &frame;
+    });
+    let error = Arc::clone(&captured);
+    ws.on_error(move |_| {
+        let _ = &error;
+    });
+    assert_eq!(Arc::strong_count(&captured), 3);
+
+    ws.clear_handlers();
+    assert_eq!(Arc::strong_count(&captured), 1);
+}
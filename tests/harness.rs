@@ -0,0 +1,649 @@
+//! Handlers driven through the in-memory `TestHarness`. Needs the `test`
+//! feature, but no native library.
+
+#![cfg(feature = "test")]
+
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+use ws::{ClosureStatus, Endpoint, Opcode, Settings, TestHarness, WsError};
+
+#[test]
+fn setup_only_needs_the_events_the_c_api_defines() {
+    for endpoint in [Endpoint::Client, Endpoint::Server] {
+        let harness = TestHarness::new();
+        harness
+            .websocket()
+            .setup(&Settings::default_for(endpoint))
+            .expect("setup");
+    }
+}
+
+#[test]
+fn open_hands_the_native_library_the_address_resolved_within_connect_timeout() {
+    let harness = TestHarness::new();
+    let ws = harness.websocket();
+    ws.setup(
+        &Settings::builder()
+            .endpoint(Endpoint::Client)
+            .connect_timeout(2000)
+            .build()
+            .expect("settings"),
+    )
+    .expect("setup");
+    ws.open(c"127.0.0.1", 80).expect("open");
+    ws.open(c"::1", 443).expect("open");
+    assert_eq!(
+        harness.opens(),
+        [("127.0.0.1".to_owned(), 80), ("::1".to_owned(), 443)]
+    );
+}
+
+#[test]
+fn open_without_connect_timeout_passes_the_host_through() {
+    let harness = TestHarness::new();
+    let ws = harness.websocket();
+    ws.setup(&Settings::default_for(Endpoint::Client))
+        .expect("setup");
+    ws.open(c"example.invalid", 80).expect("open");
+    assert_eq!(harness.opens(), [("example.invalid".to_owned(), 80)]);
+}
+
+#[test]
+fn unix_sockets_are_unsupported() {
+    let harness = TestHarness::new();
+    let ws = harness.websocket();
+    ws.setup(&Settings::default_for(Endpoint::Server))
+        .expect("setup");
+    assert!(matches!(
+        ws.bind_unix("/tmp/ws.sock"),
+        Err(WsError::Unsupported { .. })
+    ));
+    assert!(matches!(
+        ws.bind(c"unix:/tmp/ws.sock", None),
+        Err(WsError::Unsupported { .. })
+    ));
+}
+
+#[test]
+fn handshake_timeout_reports_the_open_that_did_not_complete() {
+    let harness = TestHarness::new();
+    let ws = harness.websocket();
+    let (error_tx, error_rx) = mpsc::channel();
+    ws.on_error(move |message| {
+        let _ = error_tx.send(message.to_owned());
+    });
+    ws.setup(
+        &Settings::builder()
+            .endpoint(Endpoint::Client)
+            .handshake_timeout(50)
+            .build()
+            .expect("settings"),
+    )
+    .expect("setup");
+    ws.open(c"stalled.invalid", 443).expect("open");
+    ws.open(c"127.0.0.1", 0).expect("open");
+    // The second open completes; the loopback reports 127.0.0.1:0.
+    harness.connect();
+    thread::sleep(Duration::from_millis(100));
+    ws.operate();
+    let errors: Vec<String> = error_rx.try_iter().collect();
+    assert_eq!(errors.len(), 1, "{errors:?}");
+    assert!(errors[0].contains("stalled.invalid:443"), "{errors:?}");
+}
+
+#[test]
+fn upgrade_matching_no_open_leaves_them_to_time_out() {
+    let harness = TestHarness::new();
+    let ws = harness.websocket();
+    let (error_tx, error_rx) = mpsc::channel();
+    ws.on_error(move |message| {
+        let _ = error_tx.send(message.to_owned());
+    });
+    ws.setup(
+        &Settings::builder()
+            .endpoint(Endpoint::Client)
+            .handshake_timeout(20)
+            .build()
+            .expect("settings"),
+    )
+    .expect("setup");
+    ws.open(c"stalled.invalid", 443).expect("open");
+    // The loopback reports 127.0.0.1:0, which answers neither host nor port.
+    harness.connect();
+    thread::sleep(Duration::from_millis(40));
+    ws.operate();
+    let errors: Vec<String> = error_rx.try_iter().collect();
+    assert_eq!(errors.len(), 1, "{errors:?}");
+    assert!(errors[0].contains("stalled.invalid:443"), "{errors:?}");
+}
+
+#[test]
+fn upgrade_completing_after_the_handshake_timeout_is_closed() {
+    let harness = TestHarness::new();
+    let ws = harness.websocket();
+    let (open_tx, open_rx) = mpsc::channel();
+    ws.on_open(move |conn, _| {
+        let _ = open_tx.send(conn.id());
+    });
+    ws.setup(
+        &Settings::builder()
+            .endpoint(Endpoint::Client)
+            .handshake_timeout(20)
+            .build()
+            .expect("settings"),
+    )
+    .expect("setup");
+    ws.open(c"127.0.0.1", 0).expect("open");
+    thread::sleep(Duration::from_millis(40));
+    ws.operate();
+
+    harness.connect();
+    assert!(open_rx.try_recv().is_err(), "late upgrade reached on_open");
+    assert_eq!(ws.connection_count(), 0);
+    let mut payload = 1001u16.to_be_bytes().to_vec();
+    payload.extend_from_slice(b"handshake timed out");
+    assert_eq!(harness.sent_frames(), [(Opcode::Close, payload)]);
+
+    // Only the attempt that timed out is closed.
+    harness.connect();
+    assert!(open_rx.try_recv().is_ok());
+}
+
+#[test]
+fn ping_is_answered_with_matching_pong() {
+    let harness = TestHarness::new();
+    harness.connect();
+    harness.feed_frame(Opcode::Ping, b"are you there");
+    assert_eq!(
+        harness.sent_frames(),
+        [(Opcode::Pong, b"are you there".to_vec())]
+    );
+}
+
+#[test]
+fn fragments_reach_the_message_handler_as_one_message() {
+    let harness = TestHarness::new();
+    let (message_tx, message_rx) = mpsc::channel();
+    harness.websocket().on_message(move |_, opcode, data| {
+        let _ = message_tx.send((opcode, data.to_vec()));
+    });
+    harness.connect();
+    harness.feed_frame(Opcode::Text, b"hello ");
+    harness.feed_frame(Opcode::Continuation, b"world");
+    harness.websocket().operate();
+    assert_eq!(
+        message_rx.try_recv(),
+        Ok((Opcode::Text, b"hello world".to_vec()))
+    );
+}
+
+#[test]
+fn invalid_utf8_closes_with_invalid_payload() {
+    let harness = TestHarness::new();
+    let (close_tx, close_rx) = mpsc::channel();
+    harness.websocket().on_close(move |_, status, _| {
+        let _ = close_tx.send(status);
+    });
+    harness
+        .websocket()
+        .setup(
+            &Settings::builder()
+                .endpoint(Endpoint::Server)
+                .strict_utf8(true)
+                .build()
+                .expect("settings"),
+        )
+        .expect("setup");
+    harness.connect();
+    harness.feed_frame(Opcode::Text, &[0xff, 0xfe]);
+    let sent = harness.sent_frames();
+    assert_eq!(sent.len(), 1);
+    assert_eq!(sent[0].0, Opcode::Close);
+    assert_eq!(sent[0].1[..2], 1007u16.to_be_bytes());
+
+    harness.disconnect(ClosureStatus::InvalidPayload);
+    assert_eq!(close_rx.try_recv(), Ok(ClosureStatus::InvalidPayload));
+}
+
+#[test]
+fn raised_message_limit_admits_larger_message() {
+    let harness = TestHarness::new();
+    let (message_tx, message_rx) = mpsc::channel();
+    harness.websocket().on_frame(|conn, opcode, data| {
+        if opcode == Opcode::Binary && data == b"token" {
+            conn.set_message_limit(64).expect("connection is open");
+        }
+    });
+    harness.websocket().on_message(move |_, _, data| {
+        let _ = message_tx.send(data.to_vec());
+    });
+    harness
+        .websocket()
+        .setup(
+            &Settings::builder()
+                .endpoint(Endpoint::Server)
+                .message_limit(8)
+                .build()
+                .expect("settings"),
+        )
+        .expect("setup");
+    harness.connect();
+
+    harness.feed_frame(Opcode::Binary, b"token");
+    harness.feed_frame(Opcode::Text, b"twelve");
+    harness.feed_frame(Opcode::Continuation, b" bytes");
+    harness.websocket().operate();
+
+    assert_eq!(message_rx.try_iter().last(), Some(b"twelve bytes".to_vec()));
+    assert!(
+        harness.sent_frames().is_empty(),
+        "the connection was closed"
+    );
+}
+
+#[test]
+fn inbound_frames_update_last_activity() {
+    let harness = TestHarness::new();
+    let id = harness.connect();
+    let opened = harness
+        .websocket()
+        .last_activity(id)
+        .expect("an open connection has an activity time");
+
+    thread::sleep(Duration::from_millis(5));
+    harness.feed_frame(Opcode::Pong, b"");
+    let ponged = harness.websocket().last_activity(id).expect("still open");
+    assert!(ponged > opened);
+
+    harness.disconnect(ClosureStatus::Normal);
+    assert_eq!(harness.websocket().last_activity(id), None);
+}
+
+#[test]
+fn unanswered_close_is_given_up_after_close_timeout() {
+    let harness = TestHarness::new();
+    let (close_tx, close_rx) = mpsc::channel();
+    harness.websocket().on_close(move |_, status, _| {
+        let _ = close_tx.send(status);
+    });
+    harness
+        .websocket()
+        .setup(
+            &Settings::builder()
+                .endpoint(Endpoint::Server)
+                .close_timeout(10)
+                .build()
+                .expect("settings"),
+        )
+        .expect("setup");
+    let id = harness.connect();
+    harness
+        .websocket()
+        .close(id, ClosureStatus::Normal, "bye")
+        .expect("close");
+    harness.websocket().operate();
+    assert!(close_rx.try_recv().is_err(), "closed before the timeout");
+
+    thread::sleep(Duration::from_millis(20));
+    harness.websocket().operate();
+    assert_eq!(close_rx.try_recv(), Ok(ClosureStatus::Abnormal));
+    assert_eq!(harness.sent_frames().len(), 1, "only the close frame");
+
+    // The native library's own close, once it comes, is not reported again.
+    harness.disconnect(ClosureStatus::Normal);
+    assert!(close_rx.try_recv().is_err());
+}
+
+#[test]
+fn fd_reused_after_an_abandoned_connection_opens_afresh() {
+    let harness = TestHarness::new();
+    let (open_tx, open_rx) = mpsc::channel();
+    let (frame_tx, frame_rx) = mpsc::channel();
+    let (close_tx, close_rx) = mpsc::channel();
+    let ws = harness.websocket();
+    ws.on_open(move |conn, _| {
+        let _ = open_tx.send(conn.id());
+    });
+    ws.on_frame(move |_, _, data| {
+        let _ = frame_tx.send(data.to_vec());
+    });
+    ws.on_close(move |_, status, _| {
+        let _ = close_tx.send(status);
+    });
+    ws.setup(
+        &Settings::builder()
+            .endpoint(Endpoint::Server)
+            .close_timeout(10)
+            .build()
+            .expect("settings"),
+    )
+    .expect("setup");
+    let id = harness.connect();
+    ws.close(id, ClosureStatus::Normal, "").expect("close");
+    thread::sleep(Duration::from_millis(20));
+    ws.operate();
+    assert_eq!(close_rx.try_recv(), Ok(ClosureStatus::Abnormal));
+    let _ = open_rx.try_recv();
+
+    // No native close for the abandoned socket before its fd comes back.
+    assert_eq!(harness.reconnect(), id);
+    assert_eq!(open_rx.try_recv(), Ok(id));
+    harness.feed_frame(Opcode::Text, b"hello");
+    assert_eq!(frame_rx.try_recv().as_deref(), Ok(&b"hello"[..]));
+    ws.send_text(id, "reply").expect("send");
+    harness.disconnect(ClosureStatus::Normal);
+    assert_eq!(close_rx.try_recv(), Ok(ClosureStatus::Normal));
+}
+
+#[test]
+fn broadcast_prunes_a_connection_whose_emit_failed() {
+    let harness = TestHarness::new();
+    let (close_tx, close_rx) = mpsc::channel();
+    harness.websocket().on_close(move |id, status, _| {
+        let _ = close_tx.send((id, status));
+    });
+    let first = harness.connect();
+    let second = harness.connect();
+    let third = harness.connect();
+    harness.break_connection(second);
+
+    let ws = harness.websocket();
+    assert_eq!(ws.broadcast_text("news").expect("broadcast"), 2);
+    assert_eq!(
+        harness.sent_frames(),
+        [
+            (Opcode::Text, b"news".to_vec()),
+            (Opcode::Text, b"news".to_vec()),
+        ]
+    );
+    assert_eq!(close_rx.try_recv(), Ok((second, ClosureStatus::Abnormal)));
+    assert_eq!(ws.connection_count(), 2);
+    assert!(ws.connection(second).is_none());
+    assert!(ws.connection(first).is_some() && ws.connection(third).is_some());
+}
+
+#[test]
+fn sends_after_close_fail_with_connection_closed() {
+    let harness = TestHarness::new();
+    let ws = harness.websocket();
+    let id = harness.connect();
+
+    let too_long = "x".repeat(124);
+    assert!(matches!(
+        ws.close(id, ClosureStatus::Normal, &too_long),
+        Err(WsError::ControlFrameTooLarge { len: 126 })
+    ));
+    ws.close(id, ClosureStatus::GoingAway, "restarting")
+        .expect("close");
+    let mut payload = 1001u16.to_be_bytes().to_vec();
+    payload.extend_from_slice(b"restarting");
+    assert_eq!(harness.sent_frames(), [(Opcode::Close, payload)]);
+
+    assert!(matches!(
+        ws.send_text(id, "late"),
+        Err(WsError::ConnectionClosed { id: closed }) if closed == id
+    ));
+    assert!(matches!(
+        ws.close(id, ClosureStatus::Normal, ""),
+        Err(WsError::ConnectionClosed { .. })
+    ));
+    assert_eq!(harness.sent_frames().len(), 1);
+}
+
+#[test]
+fn only_application_close_codes_without_a_variant_are_sent() {
+    let harness = TestHarness::new();
+    let ws = harness.websocket();
+    for code in [1004, 1016, 2999, 5000] {
+        let id = harness.connect();
+        assert!(
+            matches!(
+                ws.close(id, ClosureStatus::Unknown(code), ""),
+                Err(WsError::InvalidCloseStatus { code: refused }) if i32::from(refused) == code
+            ),
+            "{code} was accepted"
+        );
+    }
+    assert!(harness.sent_frames().is_empty());
+    for code in [3000, 4999] {
+        let id = harness.connect();
+        ws.close(id, ClosureStatus::Unknown(code), "")
+            .unwrap_or_else(|e| panic!("{code}: {e}"));
+    }
+    assert_eq!(harness.sent_frames().len(), 2);
+}
+
+#[test]
+fn connection_past_max_connections_is_refused() {
+    let harness = TestHarness::new();
+    let ws = harness.websocket();
+    let (open_tx, open_rx) = mpsc::channel();
+    ws.on_open(move |conn, _| {
+        let _ = open_tx.send(conn.id());
+    });
+    let (error_tx, error_rx) = mpsc::channel();
+    ws.on_error(move |message| {
+        let _ = error_tx.send(message.to_owned());
+    });
+    ws.setup(
+        &Settings::builder()
+            .endpoint(Endpoint::Server)
+            .max_connections(2)
+            .build()
+            .expect("settings"),
+    )
+    .expect("setup");
+
+    let first = harness.connect();
+    let second = harness.connect();
+    assert_eq!(ws.connection_count(), 2);
+    assert!(harness.sent_frames().is_empty());
+
+    let third = harness.connect();
+    assert_eq!(open_rx.try_iter().collect::<Vec<_>>(), [first, second]);
+    assert_eq!(ws.connection_count(), 2);
+    assert_eq!(error_rx.try_iter().count(), 1);
+    let sent = harness.sent_frames();
+    assert_eq!(sent.len(), 1);
+    assert_eq!(sent[0].0, Opcode::Close);
+    assert_eq!(sent[0].1[..2], 1008u16.to_be_bytes());
+    assert!(ws.connections().all(|info| info.id != third));
+}
+
+#[test]
+fn changing_the_ping_interval_keeps_connections_open() {
+    let harness = TestHarness::new();
+    let ws = harness.websocket();
+    let (message_tx, message_rx) = mpsc::channel();
+    ws.on_message(move |_, _, data| {
+        let _ = message_tx.send(data.to_vec());
+    });
+    let mut settings = Settings::builder()
+        .endpoint(Endpoint::Server)
+        .ping_interval(30_000)
+        .ping_timeout(5_000)
+        .build()
+        .expect("settings");
+    ws.setup(&settings).expect("setup");
+    let id = harness.connect();
+
+    ws.set_ping_interval(&mut settings, 10_000)
+        .expect("set_ping_interval");
+    assert_eq!(settings.ping_interval(), 10_000);
+    assert!(matches!(
+        ws.set_ping_interval(&mut settings, 5_000),
+        Err(WsError::InvalidSetting {
+            name: "ping_timeout",
+            ..
+        })
+    ));
+    assert_eq!(settings.ping_interval(), 10_000);
+
+    assert_eq!(ws.connection_count(), 1);
+    assert!(ws.last_activity(id).is_some());
+    harness.feed_frame(Opcode::Binary, b"still here");
+    ws.operate();
+    assert_eq!(message_rx.try_recv(), Ok(b"still here".to_vec()));
+    assert!(harness.sent_frames().is_empty());
+}
+
+#[test]
+fn flooding_past_max_frames_per_sec_closes_the_connection() {
+    let harness = TestHarness::new();
+    let ws = harness.websocket();
+    let (frame_tx, frame_rx) = mpsc::channel();
+    ws.on_frame(move |_, _, data| {
+        let _ = frame_tx.send(data.to_vec());
+    });
+    let (error_tx, error_rx) = mpsc::channel();
+    ws.on_error_detailed(move |id, _, _| {
+        let _ = error_tx.send(id);
+    });
+    ws.setup(
+        &Settings::builder()
+            .endpoint(Endpoint::Server)
+            .max_frames_per_sec(5)
+            .build()
+            .expect("settings"),
+    )
+    .expect("setup");
+    let id = harness.connect();
+
+    for _ in 0..6 {
+        harness.feed_frame(Opcode::Binary, b"flood");
+    }
+    assert_eq!(frame_rx.try_iter().count(), 5, "the sixth frame is dropped");
+    assert_eq!(error_rx.try_iter().collect::<Vec<_>>(), [Some(id)]);
+    let sent = harness.sent_frames();
+    assert_eq!(sent.len(), 1);
+    assert_eq!(sent[0].0, Opcode::Close);
+    assert_eq!(sent[0].1[..2], 1008u16.to_be_bytes());
+}
+
+#[test]
+fn replaced_frame_handler_receives_later_frames() {
+    let harness = TestHarness::new();
+    let ws = harness.websocket();
+    let (old_tx, old_rx) = mpsc::channel();
+    ws.on_frame(move |_, _, data| {
+        let _ = old_tx.send(data.to_vec());
+    });
+    harness.connect();
+    harness.feed_frame(Opcode::Text, b"login");
+
+    let (new_tx, new_rx) = mpsc::channel();
+    assert!(ws.replace_frame_handler(move |_, _, data| {
+        let _ = new_tx.send(data.to_vec());
+    }));
+    harness.feed_frame(Opcode::Text, b"authenticated");
+
+    assert_eq!(old_rx.recv(), Ok(b"login".to_vec()));
+    // The old handler, and the sender it held, is gone.
+    assert!(old_rx.recv().is_err());
+    assert_eq!(
+        new_rx.try_iter().collect::<Vec<_>>(),
+        [b"authenticated".to_vec()]
+    );
+}
+
+#[test]
+fn reserved_opcode_closes_with_protocol_error() {
+    let harness = TestHarness::new();
+    let ws = harness.websocket();
+    let (frame_tx, frame_rx) = mpsc::channel();
+    ws.on_frame(move |_, opcode, _| {
+        let _ = frame_tx.send(opcode);
+    });
+    let (error_tx, error_rx) = mpsc::channel();
+    ws.on_error(move |message| {
+        let _ = error_tx.send(message.to_owned());
+    });
+    let id = harness.connect();
+    harness.feed_frame(Opcode::Unknown(5), b"");
+
+    assert!(frame_rx.try_recv().is_err(), "the frame reached on_frame");
+    assert_eq!(error_rx.try_iter().count(), 1);
+    let sent = harness.sent_frames();
+    assert_eq!(sent.len(), 1);
+    assert_eq!(sent[0].0, Opcode::Close);
+    assert_eq!(sent[0].1[..2], 1002u16.to_be_bytes());
+    assert!(matches!(
+        ws.send_text(id, "late"),
+        Err(WsError::ConnectionClosed { .. })
+    ));
+}
+
+#[test]
+fn reserved_opcode_is_delivered_without_strict_protocol() {
+    let harness = TestHarness::new();
+    let ws = harness.websocket();
+    let (frame_tx, frame_rx) = mpsc::channel();
+    ws.on_frame(move |_, opcode, _| {
+        let _ = frame_tx.send(opcode);
+    });
+    ws.setup(
+        &Settings::builder()
+            .endpoint(Endpoint::Server)
+            .strict_protocol(false)
+            .build()
+            .expect("settings"),
+    )
+    .expect("setup");
+    harness.connect();
+    harness.feed_frame(Opcode::Unknown(5), b"");
+
+    assert_eq!(frame_rx.try_recv(), Ok(Opcode::Unknown(5)));
+    assert!(harness.sent_frames().is_empty());
+}
+
+#[test]
+fn client_and_server_contexts_report_their_own_roles() {
+    let (role_tx, role_rx) = mpsc::channel();
+    let harnesses: Vec<TestHarness> = [Endpoint::Client, Endpoint::Server]
+        .into_iter()
+        .map(|endpoint| {
+            let harness = TestHarness::new();
+            let role_tx = role_tx.clone();
+            // The same callback for both roles.
+            harness.websocket().on_frame(move |conn, _, _| {
+                let _ = role_tx.send((conn.endpoint(), conn.is_client(), conn.is_server()));
+            });
+            harness
+                .websocket()
+                .setup(&Settings::default_for(endpoint))
+                .expect("setup");
+            harness.connect();
+            harness
+        })
+        .collect();
+    for harness in &harnesses {
+        harness.feed_frame(Opcode::Text, b"who am I");
+    }
+    assert_eq!(
+        role_rx.try_iter().collect::<Vec<_>>(),
+        [
+            (Endpoint::Client, true, false),
+            (Endpoint::Server, false, true),
+        ]
+    );
+}
+
+#[test]
+fn settings_can_be_dropped_once_setup_returns() {
+    let harness = TestHarness::new();
+    let ws = harness.websocket();
+    for origin in ["https://a.example", "https://b.example"] {
+        let settings = Settings::builder()
+            .endpoint(Endpoint::Server)
+            .host("localhost:4433")
+            .allowed_origin(origin)
+            .build()
+            .expect("settings");
+        ws.setup(&settings).expect("setup");
+        drop(settings);
+    }
+    harness.connect();
+    harness.feed_frame(Opcode::Ping, b"");
+    assert_eq!(harness.sent_frames(), [(Opcode::Pong, Vec::new())]);
+}
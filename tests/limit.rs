@@ -0,0 +1,111 @@
+//! Each `LimitAction` applied to an oversized message.
+//!
+//! Needs the native library to be loadable (see the README's search order).
+
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+use ws::{ClosureStatus, Endpoint, LimitAction, Opcode, Settings, WebSocket};
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+const LIMIT: usize = 16;
+
+/// What the server saw: a text frame with its truncation flag, or a close.
+#[derive(Debug, PartialEq)]
+enum Seen {
+    Text(String, bool),
+    Closed(ClosureStatus),
+}
+
+/// Sends an oversized text frame, then a small one, to a server applying
+/// `action`, and returns what the server saw until the small frame or a
+/// close arrived.
+fn exchange(action: LimitAction) -> Vec<Seen> {
+    let (seen_tx, seen_rx) = mpsc::channel();
+    let (port_tx, port_rx) = mpsc::channel();
+
+    let close_tx = seen_tx.clone();
+    let server = WebSocket::spawn(move |ws| {
+        ws.on_frame(move |conn, opcode, data| {
+            if opcode == Opcode::Text {
+                let text = String::from_utf8_lossy(data).into_owned();
+                let _ = seen_tx.send(Seen::Text(text, conn.is_truncated()));
+            }
+        });
+        ws.on_close(move |_, status, _| {
+            let _ = close_tx.send(Seen::Closed(status));
+        });
+        ws.setup(
+            &Settings::builder()
+                .endpoint(Endpoint::Server)
+                .host("localhost")
+                .message_limit(LIMIT)
+                .limit_action(action)
+                .build()?,
+        )?;
+        let _ = port_tx.send(ws.bind(c"localhost", None)?);
+        Ok(())
+    })
+    .expect("server failed to start");
+    let port = port_rx.recv().expect("server did not report a port");
+
+    let client = WebSocket::new().expect("client context");
+    client.on_open(|conn, _| {
+        conn.send_text(&"x".repeat(4 * LIMIT)).expect("send");
+        conn.send_text("after").expect("send");
+    });
+    client
+        .setup(
+            &Settings::builder()
+                .endpoint(Endpoint::Client)
+                .host(format!("localhost:{port}"))
+                .build()
+                .expect("client settings"),
+        )
+        .expect("client setup");
+    client.open(c"localhost", port).expect("client open");
+
+    let mut seen = Vec::new();
+    let deadline = Instant::now() + TIMEOUT;
+    loop {
+        while let Ok(event) = seen_rx.try_recv() {
+            let done = matches!(&event, Seen::Closed(_))
+                || matches!(&event, Seen::Text(text, _) if text == "after");
+            seen.push(event);
+            if done {
+                drop(client);
+                server.stop();
+                server.join().expect("server thread panicked");
+                return seen;
+            }
+        }
+        assert!(Instant::now() < deadline, "server saw {seen:?} only");
+        client.operate_with_timeout(Duration::from_millis(1));
+    }
+}
+
+#[test]
+fn close_action_closes_with_message_too_big() {
+    assert_eq!(
+        exchange(LimitAction::Close),
+        [Seen::Closed(ClosureStatus::MessageTooBig)]
+    );
+}
+
+#[test]
+fn ignore_action_drops_the_message() {
+    assert_eq!(
+        exchange(LimitAction::Ignore),
+        [Seen::Text("after".into(), false)]
+    );
+}
+
+#[test]
+fn truncate_action_delivers_the_limit() {
+    assert_eq!(
+        exchange(LimitAction::Truncate),
+        [
+            Seen::Text("x".repeat(LIMIT), true),
+            Seen::Text("after".into(), false),
+        ]
+    );
+}
@@ -0,0 +1,829 @@
+//! Client/server round trip through the native library.
+//!
+//! Needs the native library to be loadable (see the README's search order).
+
+use std::cell::Cell;
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::pin::pin;
+use std::sync::{Arc, mpsc};
+use std::task::{Context as TaskContext, Poll, Wake, Waker};
+use std::thread;
+use std::time::{Duration, Instant};
+use ws::{
+    ClientReconnect, ClosureStatus, Endpoint, Frame, Opcode, OperateOutcome, RequestMap, Settings,
+    StopReason, WebSocket, WsError,
+};
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+#[test]
+fn text_frame_reaches_server() {
+    let (frame_tx, frame_rx) = mpsc::channel();
+    let (port_tx, port_rx) = mpsc::channel();
+
+    let server = WebSocket::spawn(move |ws| {
+        ws.on_frame(move |conn, opcode, data| {
+            if opcode == Opcode::Text {
+                let _ = frame_tx.send((conn.endpoint(), data.to_vec()));
+            }
+        });
+        ws.setup(
+            &Settings::builder()
+                .endpoint(Endpoint::Server)
+                .host("localhost")
+                .build()?,
+        )?;
+        let _ = port_tx.send(ws.bind(c"localhost", None)?);
+        Ok(())
+    })
+    .expect("server failed to start");
+    let port = port_rx.recv().expect("server did not report a port");
+    assert_ne!(port, 0, "bind should report the ephemeral port");
+
+    // Built at runtime, so it goes through `open_str` rather than a literal.
+    let host = String::from("localhost");
+    let client = WebSocket::new().expect("client context");
+    let (role_tx, role_rx) = mpsc::channel();
+    client.on_open(move |conn, _| {
+        let _ = role_tx.send(conn.endpoint());
+        conn.send_text("round trip").expect("send");
+    });
+    client
+        .setup(
+            &Settings::builder()
+                .endpoint(Endpoint::Client)
+                .host(format!("{host}:{port}"))
+                .build()
+                .expect("client settings"),
+        )
+        .expect("client setup");
+    client.open_str(&host, port).expect("client open");
+
+    let deadline = Instant::now() + TIMEOUT;
+    let received = loop {
+        if let Ok(data) = frame_rx.try_recv() {
+            break data;
+        }
+        assert!(Instant::now() < deadline, "server never saw the frame");
+        let outcome = client.operate_with_timeout(Duration::from_millis(1));
+        assert_ne!(outcome, OperateOutcome::Stopped, "client stopped early");
+    };
+    assert_eq!(received, (Endpoint::Server, b"round trip".to_vec()));
+    assert_eq!(role_rx.try_recv(), Ok(Endpoint::Client));
+
+    drop(client);
+    server.stop();
+    server.join().expect("server thread panicked");
+}
+
+#[test]
+fn binding_port_zero_reports_the_assigned_port() {
+    let server = WebSocket::new().expect("server context");
+    server
+        .setup(&Settings::default_for(Endpoint::Server))
+        .expect("server setup");
+    let port = server.bind(c"127.0.0.1", None).expect("bind");
+    assert_ne!(port, 0);
+    // The port reported is the one actually listening.
+    TcpStream::connect(("127.0.0.1", port)).expect("connect to the reported port");
+}
+
+#[test]
+fn socket_options_read_back_after_setting() {
+    let (port_tx, port_rx) = mpsc::channel();
+    let server = WebSocket::spawn(move |ws| {
+        ws.setup(
+            &Settings::builder()
+                .endpoint(Endpoint::Server)
+                .host("localhost")
+                .build()?,
+        )?;
+        let _ = port_tx.send(ws.bind(c"localhost", None)?);
+        Ok(())
+    })
+    .expect("server failed to start");
+    let port = port_rx.recv().expect("server did not report a port");
+
+    let (open_tx, open_rx) = mpsc::channel();
+    let client = WebSocket::new().expect("client context");
+    client.on_open(move |conn, _| {
+        let _ = open_tx.send(conn.id());
+    });
+    client
+        .setup(
+            &Settings::builder()
+                .endpoint(Endpoint::Client)
+                .host(format!("localhost:{port}"))
+                .build()
+                .expect("client settings"),
+        )
+        .expect("client setup");
+    client.open(c"localhost", port).expect("client open");
+    let deadline = Instant::now() + TIMEOUT;
+    let id = loop {
+        if let Ok(id) = open_rx.try_recv() {
+            break id;
+        }
+        assert!(Instant::now() < deadline, "the client never opened");
+        client.operate_with_timeout(Duration::from_millis(1));
+    };
+
+    for enabled in [true, false] {
+        client.set_nodelay(id, enabled).expect("set TCP_NODELAY");
+        assert_eq!(client.nodelay(id).expect("get TCP_NODELAY"), enabled);
+        client.set_keepalive(id, enabled).expect("set SO_KEEPALIVE");
+        assert_eq!(client.keepalive(id).expect("get SO_KEEPALIVE"), enabled);
+    }
+
+    drop(client);
+    server.stop();
+    server.join().expect("server thread panicked");
+}
+
+#[test]
+fn payload_read_in_chunks_reaches_the_server_whole() {
+    const LEN: usize = 10 * 1024 * 1024;
+    let payload: Vec<u8> = (0..LEN).map(|i| (i % 251) as u8).collect();
+
+    let (message_tx, message_rx) = mpsc::channel();
+    let (port_tx, port_rx) = mpsc::channel();
+    let server = WebSocket::spawn(move |ws| {
+        ws.on_message(move |_, opcode, data| {
+            let _ = message_tx.send((opcode, data.to_vec()));
+        });
+        ws.setup(
+            &Settings::builder()
+                .endpoint(Endpoint::Server)
+                .host("localhost")
+                .message_limit(2 * LEN)
+                .build()?,
+        )?;
+        let _ = port_tx.send(ws.bind(c"localhost", None)?);
+        Ok(())
+    })
+    .expect("server failed to start");
+    let port = port_rx.recv().expect("server did not report a port");
+
+    let (open_tx, open_rx) = mpsc::channel();
+    let client = WebSocket::new().expect("client context");
+    client.on_open(move |conn, _| {
+        let _ = open_tx.send(conn.id());
+    });
+    client
+        .setup(
+            &Settings::builder()
+                .endpoint(Endpoint::Client)
+                .host(format!("localhost:{port}"))
+                .build()
+                .expect("client settings"),
+        )
+        .expect("client setup");
+    client.open(c"localhost", port).expect("client open");
+
+    let deadline = Instant::now() + TIMEOUT;
+    let mut sent = false;
+    let received = loop {
+        if let Ok(message) = message_rx.try_recv() {
+            break message;
+        }
+        if let Ok(id) = open_rx.try_recv() {
+            let frame = Frame::read_from(Opcode::Binary, &payload[..], 64 * 1024).expect("read");
+            assert_eq!(frame.len(), LEN);
+            client.emit(id, &frame).expect("emit");
+            sent = true;
+        }
+        assert!(Instant::now() < deadline, "server never saw the message");
+        client.operate_with_timeout(Duration::from_millis(1));
+    };
+    assert!(sent);
+    assert_eq!(received.0, Opcode::Binary);
+    assert!(received.1 == payload, "payload differs");
+
+    drop(client);
+    server.stop();
+    server.join().expect("server thread panicked");
+}
+
+/// Polls `future` to completion on the current thread, parking between
+/// wakes.
+fn block_on<F: Future>(future: F) -> F::Output {
+    struct Unpark(thread::Thread);
+    impl Wake for Unpark {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+    let waker = Waker::from(Arc::new(Unpark(thread::current())));
+    let mut cx = TaskContext::from_waker(&waker);
+    let mut future = pin!(future);
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+            return output;
+        }
+        thread::park();
+    }
+}
+
+#[test]
+fn request_map_matches_responses_and_times_out() {
+    let (port_tx, port_rx) = mpsc::channel();
+    // Echoes every message but those starting with "drop".
+    let server = WebSocket::spawn(move |ws| {
+        ws.on_message(|conn, _, data| {
+            if !data.starts_with(b"drop") {
+                conn.send_binary(data).expect("echo");
+            }
+        });
+        ws.setup(
+            &Settings::builder()
+                .endpoint(Endpoint::Server)
+                .host("localhost")
+                .build()?,
+        )?;
+        let _ = port_tx.send(ws.bind(c"localhost", None)?);
+        Ok(())
+    })
+    .expect("server failed to start");
+    let port = port_rx.recv().expect("server did not report a port");
+
+    // The correlation id is the payload's last byte.
+    let requests = RequestMap::new(|data: &[u8]| data.last().copied());
+    let (open_tx, open_rx) = mpsc::channel();
+    let client = WebSocket::spawn(move |ws| {
+        let resolver = requests.clone();
+        ws.on_message(move |_, _, data| {
+            resolver.resolve(data);
+        });
+        let sender = ws.sender();
+        ws.on_open(move |conn, _| {
+            let _ = open_tx.send((requests.clone(), sender.clone(), conn.id()));
+        });
+        ws.setup(
+            &Settings::builder()
+                .endpoint(Endpoint::Client)
+                .host(format!("localhost:{port}"))
+                .build()?,
+        )?;
+        ws.open(c"localhost", port)?;
+        Ok(())
+    })
+    .expect("client failed to start");
+    let (requests, sender, id) = open_rx.recv_timeout(TIMEOUT).expect("client never opened");
+
+    let frame = |payload: &[u8]| {
+        let mut frame = Frame::with_opcode(Opcode::Binary).expect("frame");
+        frame.push(payload).expect("push");
+        frame
+    };
+    let answered = requests
+        .request(&sender, id, 1, frame(b"ping\x01"), TIMEOUT)
+        .expect("request");
+    let dropped = requests
+        .request(
+            &sender,
+            id,
+            2,
+            frame(b"drop\x02"),
+            Duration::from_millis(200),
+        )
+        .expect("request");
+    assert_eq!(block_on(answered).expect("response"), b"ping\x01");
+    assert!(matches!(
+        block_on(dropped),
+        Err(WsError::RequestTimeout { .. })
+    ));
+    assert_eq!(requests.pending(), 0);
+
+    let replaced = requests
+        .request(&sender, id, 3, frame(b"drop\x03"), TIMEOUT)
+        .expect("request");
+    let replacement = requests
+        .request(&sender, id, 3, frame(b"ping\x03"), TIMEOUT)
+        .expect("request");
+    assert!(matches!(
+        block_on(replaced),
+        Err(WsError::RequestReplaced { id: replaced_id }) if replaced_id == id
+    ));
+    assert_eq!(block_on(replacement).expect("response"), b"ping\x03");
+
+    let earlier = requests
+        .request(&sender, id, 4, frame(b"drop\x04"), TIMEOUT)
+        .expect("request");
+    client.stop();
+    client.join().expect("client thread panicked");
+    // A request that cannot be queued leaves the earlier one pending.
+    assert!(matches!(
+        requests.request(&sender, id, 4, frame(b"ping\x04"), TIMEOUT),
+        Err(WsError::ContextGone)
+    ));
+    assert_eq!(requests.pending(), 1);
+    drop(earlier);
+    assert_eq!(requests.pending(), 0);
+    server.stop();
+    server.join().expect("server thread panicked");
+}
+
+#[test]
+fn batches_from_two_threads_never_interleave() {
+    const BATCHES: usize = 100;
+    const BATCH: usize = 10;
+    let (frame_tx, frame_rx) = mpsc::channel();
+    let (port_tx, port_rx) = mpsc::channel();
+    let server = WebSocket::spawn(move |ws| {
+        ws.on_frame(move |_, opcode, data| {
+            if opcode == Opcode::Text {
+                let _ = frame_tx.send(String::from_utf8_lossy(data).into_owned());
+            }
+        });
+        ws.setup(
+            &Settings::builder()
+                .endpoint(Endpoint::Server)
+                .host("localhost")
+                .build()?,
+        )?;
+        let _ = port_tx.send(ws.bind(c"localhost", None)?);
+        Ok(())
+    })
+    .expect("server failed to start");
+    let port = port_rx.recv().expect("server did not report a port");
+
+    let (open_tx, open_rx) = mpsc::channel();
+    let client = WebSocket::spawn(move |ws| {
+        let sender = ws.sender();
+        ws.on_open(move |conn, _| {
+            let _ = open_tx.send((sender.clone(), conn.id()));
+        });
+        ws.setup(
+            &Settings::builder()
+                .endpoint(Endpoint::Client)
+                .host(format!("localhost:{port}"))
+                .build()?,
+        )?;
+        ws.open(c"localhost", port)?;
+        Ok(())
+    })
+    .expect("client failed to start");
+    let (sender, id) = open_rx.recv_timeout(TIMEOUT).expect("client never opened");
+
+    // Each thread sends 1000 frames, as batches of ten "thread batch index".
+    let threads: Vec<_> = ['a', 'b']
+        .into_iter()
+        .map(|name| {
+            let sender = sender.clone();
+            thread::spawn(move || {
+                for batch in 0..BATCHES {
+                    let frames = (0..BATCH)
+                        .map(|index| Frame::text(&format!("{name} {batch} {index}")))
+                        .collect::<Result<Vec<_>, _>>()
+                        .expect("frame");
+                    sender.send_batch(id, frames).expect("send_batch");
+                }
+            })
+        })
+        .collect();
+    for thread in threads {
+        thread.join().expect("sender thread panicked");
+    }
+
+    let received: Vec<String> = (0..2 * BATCHES * BATCH)
+        .map(|_| {
+            frame_rx
+                .recv_timeout(TIMEOUT)
+                .expect("server missed frames")
+        })
+        .collect();
+    for batch in received.chunks(BATCH) {
+        let (name, number) = {
+            let mut fields = batch[0].split(' ');
+            (fields.next().unwrap(), fields.next().unwrap())
+        };
+        for (index, frame) in batch.iter().enumerate() {
+            assert_eq!(*frame, format!("{name} {number} {index}"), "{batch:?}");
+        }
+    }
+
+    client.stop();
+    client.join().expect("client thread panicked");
+    server.stop();
+    server.join().expect("server thread panicked");
+}
+
+#[test]
+fn shorter_ping_interval_applies_to_an_open_connection() {
+    let (port_tx, port_rx) = mpsc::channel();
+    let server = WebSocket::spawn(move |ws| {
+        ws.setup(
+            &Settings::builder()
+                .endpoint(Endpoint::Server)
+                .host("localhost")
+                .build()?,
+        )?;
+        let _ = port_tx.send(ws.bind(c"localhost", None)?);
+        Ok(())
+    })
+    .expect("server failed to start");
+    let port = port_rx.recv().expect("server did not report a port");
+
+    let client = WebSocket::new().expect("client context");
+    let (open_tx, open_rx) = mpsc::channel();
+    client.on_open(move |conn, _| {
+        let _ = open_tx.send(conn.id());
+    });
+    let (pong_tx, pong_rx) = mpsc::channel();
+    client.on_pong(move |_, _| {
+        let _ = pong_tx.send(());
+    });
+    let (close_tx, close_rx) = mpsc::channel();
+    client.on_close(move |_, status, _| {
+        let _ = close_tx.send(status);
+    });
+    let mut settings = Settings::builder()
+        .endpoint(Endpoint::Client)
+        .host(format!("localhost:{port}"))
+        .ping_interval(400)
+        .ping_timeout(20)
+        .build()
+        .expect("client settings");
+    client.setup(&settings).expect("client setup");
+    client.open(c"localhost", port).expect("client open");
+    let deadline = Instant::now() + TIMEOUT;
+    while open_rx.try_recv().is_err() {
+        assert!(Instant::now() < deadline, "the client never opened");
+        client.operate_with_timeout(Duration::from_millis(1));
+    }
+
+    let pongs_within = |window: Duration| {
+        let end = Instant::now() + window;
+        while Instant::now() < end {
+            client.operate_with_timeout(Duration::from_millis(1));
+        }
+        pong_rx.try_iter().count()
+    };
+    let before = pongs_within(Duration::from_secs(2));
+    client
+        .set_ping_interval(&mut settings, 50)
+        .expect("set_ping_interval");
+    // Let the ping scheduled under the old interval go out first.
+    pongs_within(Duration::from_millis(500));
+    let after = pongs_within(Duration::from_secs(2));
+
+    assert!(after > 2 * before, "{before} pongs before, {after} after");
+    assert!(close_rx.try_recv().is_err(), "the connection was closed");
+    assert_eq!(client.connection_count(), 1);
+
+    drop(client);
+    server.stop();
+    server.join().expect("server thread panicked");
+}
+
+#[test]
+fn client_reopens_after_reset() {
+    let (port_tx, port_rx) = mpsc::channel();
+    let server = WebSocket::spawn(move |ws| {
+        ws.setup(
+            &Settings::builder()
+                .endpoint(Endpoint::Server)
+                .host("localhost")
+                .build()?,
+        )?;
+        let _ = port_tx.send(ws.bind(c"localhost", None)?);
+        Ok(())
+    })
+    .expect("server failed to start");
+    let port = port_rx.recv().expect("server did not report a port");
+
+    let (open_tx, open_rx) = mpsc::channel();
+    let client = WebSocket::new().expect("client context");
+    client.on_open(move |conn, _| {
+        let _ = open_tx.send(conn.id());
+    });
+    client
+        .setup(
+            &Settings::builder()
+                .endpoint(Endpoint::Client)
+                .host(format!("localhost:{port}"))
+                .build()
+                .expect("client settings"),
+        )
+        .expect("client setup");
+
+    for attempt in 0..2 {
+        client.open(c"localhost", port).expect("client open");
+        let deadline = Instant::now() + TIMEOUT;
+        let id = loop {
+            if let Ok(id) = open_rx.try_recv() {
+                break id;
+            }
+            assert!(Instant::now() < deadline, "open {attempt} never completed");
+            client.operate_with_timeout(Duration::from_millis(1));
+        };
+        assert_eq!(client.connection_count(), 1);
+        client.reset();
+        assert_eq!(client.connection_count(), 0);
+        assert!(client.connection(id).is_none());
+    }
+
+    drop(client);
+    server.stop();
+    server.join().expect("server thread panicked");
+}
+
+#[test]
+fn handler_panic_closes_connection_and_server_keeps_running() {
+    let (port_tx, port_rx) = mpsc::channel();
+    let server = WebSocket::spawn(move |ws| {
+        ws.on_open(|_, _| panic!("open handler failed"));
+        ws.setup(
+            &Settings::builder()
+                .endpoint(Endpoint::Server)
+                .host("localhost")
+                .build()?,
+        )?;
+        let _ = port_tx.send(ws.bind(c"localhost", None)?);
+        Ok(())
+    })
+    .expect("server failed to start");
+    let port = port_rx.recv().expect("server did not report a port");
+
+    let (close_tx, close_rx) = mpsc::channel();
+    let client = WebSocket::new().expect("client context");
+    client.on_close(move |_, status, _| {
+        let _ = close_tx.send(status);
+    });
+    client
+        .setup(
+            &Settings::builder()
+                .endpoint(Endpoint::Client)
+                .host(format!("localhost:{port}"))
+                .build()
+                .expect("client settings"),
+        )
+        .expect("client setup");
+    client.open(c"localhost", port).expect("client open");
+
+    let deadline = Instant::now() + TIMEOUT;
+    let status = loop {
+        if let Ok(status) = close_rx.try_recv() {
+            break status;
+        }
+        assert!(
+            Instant::now() < deadline,
+            "server never closed the connection"
+        );
+        client.operate_with_timeout(Duration::from_millis(1));
+    };
+    assert_eq!(status, ClosureStatus::InternalError);
+    assert!(!server.is_finished(), "the panic ended the server loop");
+
+    drop(client);
+    server.stop();
+    assert_eq!(server.wait().expect("server loop"), StopReason::Stopped);
+}
+
+#[test]
+fn bare_close_reports_no_status() {
+    let (close_tx, close_rx) = mpsc::channel();
+    let (port_tx, port_rx) = mpsc::channel();
+    let server = WebSocket::spawn(move |ws| {
+        ws.on_close(move |_, status, reason| {
+            let _ = close_tx.send((status, reason));
+        });
+        ws.setup(
+            &Settings::builder()
+                .endpoint(Endpoint::Server)
+                .host("localhost")
+                .build()?,
+        )?;
+        let _ = port_tx.send(ws.bind(c"localhost", None)?);
+        Ok(())
+    })
+    .expect("server failed to start");
+    let port = port_rx.recv().expect("server did not report a port");
+
+    let client = WebSocket::new().expect("client context");
+    client.on_open(|conn, _| conn.close_bare().expect("bare close"));
+    client
+        .setup(
+            &Settings::builder()
+                .endpoint(Endpoint::Client)
+                .host(format!("localhost:{port}"))
+                .build()
+                .expect("client settings"),
+        )
+        .expect("client setup");
+    client.open(c"localhost", port).expect("client open");
+
+    let deadline = Instant::now() + TIMEOUT;
+    let closed = loop {
+        if let Ok(closed) = close_rx.try_recv() {
+            break closed;
+        }
+        assert!(Instant::now() < deadline, "server never saw the close");
+        client.operate_with_timeout(Duration::from_millis(1));
+    };
+    assert_eq!(closed, (ClosureStatus::NoStatus, None));
+
+    drop(client);
+    server.stop();
+    server.join().expect("server thread panicked");
+}
+
+#[test]
+fn handshake_timeout_fires_when_server_never_upgrades() {
+    let listener = TcpListener::bind("127.0.0.1:0").expect("listener");
+    let port = listener.local_addr().expect("local address").port();
+    // Accepts the connection and holds it without ever answering the upgrade.
+    let silent = thread::spawn(move || listener.accept().map(|(stream, _)| stream));
+
+    let (error_tx, error_rx) = mpsc::channel();
+    let client = WebSocket::new().expect("client context");
+    client.on_error(move |msg| {
+        let _ = error_tx.send(msg.to_owned());
+    });
+    client
+        .setup(
+            &Settings::builder()
+                .endpoint(Endpoint::Client)
+                .host(format!("127.0.0.1:{port}"))
+                .handshake_timeout(200)
+                .build()
+                .expect("client settings"),
+        )
+        .expect("client setup");
+    let started = Instant::now();
+    client.open(c"127.0.0.1", port).expect("client open");
+
+    let message = loop {
+        if let Ok(message) = error_rx.try_recv() {
+            break message;
+        }
+        assert!(
+            started.elapsed() < TIMEOUT,
+            "the handshake timeout never fired"
+        );
+        client.operate_with_timeout(Duration::from_millis(1));
+    };
+    assert!(
+        message.contains("did not complete the upgrade"),
+        "unexpected error: {message}"
+    );
+    assert!(started.elapsed() < Duration::from_secs(5));
+
+    drop(client);
+    drop(silent.join().expect("listener thread panicked"));
+}
+
+#[test]
+fn reconnect_retries_until_a_normal_close() {
+    let (port_tx, port_rx) = mpsc::channel();
+    let server = WebSocket::spawn(move |ws| {
+        // The first connection goes away, the second closes normally.
+        let opened = Cell::new(0);
+        ws.on_open(move |conn, _| {
+            let status = match opened.replace(opened.get() + 1) {
+                0 => ClosureStatus::GoingAway,
+                _ => ClosureStatus::Normal,
+            };
+            conn.close(status, "").expect("close");
+        });
+        ws.setup(
+            &Settings::builder()
+                .endpoint(Endpoint::Server)
+                .host("localhost")
+                .build()?,
+        )?;
+        let _ = port_tx.send(ws.bind(c"localhost", None)?);
+        Ok(())
+    })
+    .expect("server failed to start");
+    let port = port_rx.recv().expect("server did not report a port");
+
+    let settings = Settings::builder()
+        .endpoint(Endpoint::Client)
+        .host(format!("localhost:{port}"))
+        .build()
+        .expect("client settings");
+    let (configured_tx, configured_rx) = mpsc::channel();
+    let (retry_tx, retry_rx) = mpsc::channel();
+    let mut client = ClientReconnect::new(settings, c"localhost", port)
+        .base_delay(Duration::from_millis(10))
+        .configure(move |_| {
+            let _ = configured_tx.send(());
+        })
+        .on_retry(move |attempt, _| {
+            let _ = retry_tx.send(attempt);
+            true
+        });
+    let (done_tx, done_rx) = mpsc::channel();
+    thread::spawn(move || {
+        let _ = done_tx.send(client.run());
+    });
+
+    let result = done_rx
+        .recv_timeout(TIMEOUT)
+        .expect("the client never stopped reconnecting");
+    assert!(result.is_ok(), "run failed: {result:?}");
+    assert_eq!(configured_rx.try_iter().count(), 2);
+    assert_eq!(retry_rx.try_iter().collect::<Vec<_>>(), [1]);
+
+    server.stop();
+    server.join().expect("server thread panicked");
+}
+
+#[test]
+fn reconnect_gives_up_when_on_retry_declines() {
+    // Bound and dropped, so nothing listens on the port.
+    let port = TcpListener::bind("127.0.0.1:0")
+        .and_then(|listener| listener.local_addr())
+        .expect("free port")
+        .port();
+    let settings = Settings::builder()
+        .endpoint(Endpoint::Client)
+        .host(format!("127.0.0.1:{port}"))
+        .build()
+        .expect("client settings");
+    let mut retries = 0;
+    let mut client = ClientReconnect::new(settings, c"127.0.0.1", port)
+        .base_delay(Duration::from_millis(1))
+        .on_retry(move |_, _| {
+            retries += 1;
+            retries < 3
+        });
+
+    let result = client.run();
+    assert!(
+        matches!(result, Err(WsError::ReconnectGaveUp { .. })),
+        "unexpected result: {result:?}"
+    );
+}
+
+/// Sends an upgrade request carrying `origin` and returns the status line of
+/// the response, or an empty string if the server closed without one.
+fn upgrade_with_origin(port: u16, origin: &str) -> String {
+    let mut stream = TcpStream::connect(("127.0.0.1", port)).expect("connect");
+    stream
+        .set_read_timeout(Some(TIMEOUT))
+        .expect("read timeout");
+    write!(
+        stream,
+        "GET / HTTP/1.1\r\n\
+         Host: 127.0.0.1:{port}\r\n\
+         Upgrade: websocket\r\n\
+         Connection: Upgrade\r\n\
+         Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+         Sec-WebSocket-Version: 13\r\n\
+         Origin: {origin}\r\n\
+         \r\n"
+    )
+    .expect("send upgrade request");
+    let mut response = Vec::new();
+    let mut buf = [0; 1024];
+    while !response.windows(2).any(|w| w == b"\r\n") {
+        match stream.read(&mut buf) {
+            Ok(0) | Err(_) => break,
+            Ok(n) => response.extend_from_slice(&buf[..n]),
+        }
+    }
+    let response = String::from_utf8_lossy(&response);
+    response.lines().next().unwrap_or_default().to_owned()
+}
+
+#[test]
+fn disallowed_origin_is_closed_without_opening() {
+    let (port_tx, port_rx) = mpsc::channel();
+    let (open_tx, open_rx) = mpsc::channel();
+    let server = WebSocket::spawn(move |ws| {
+        ws.on_open(move |_, _| {
+            let _ = open_tx.send(());
+        });
+        ws.setup(
+            &Settings::builder()
+                .endpoint(Endpoint::Server)
+                .host("127.0.0.1")
+                .allowed_origin("https://example.com")
+                .build()?,
+        )?;
+        let _ = port_tx.send(ws.bind(c"127.0.0.1", None)?);
+        Ok(())
+    })
+    .expect("server failed to start");
+    let port = port_rx.recv().expect("server did not report a port");
+
+    let status = upgrade_with_origin(port, "https://evil.example");
+    assert!(
+        !status.contains(" 101 "),
+        "disallowed origin was upgraded: {status:?}"
+    );
+    assert!(open_rx.recv_timeout(Duration::from_millis(200)).is_err());
+
+    let status = upgrade_with_origin(port, "https://example.com");
+    assert!(
+        status.contains(" 101 "),
+        "allowed origin was refused: {status:?}"
+    );
+    assert_eq!(open_rx.recv_timeout(TIMEOUT), Ok(()));
+
+    server.stop();
+    server.join().expect("server thread panicked");
+}
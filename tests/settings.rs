@@ -0,0 +1,196 @@
+//! Settings defaults and validation. Needs no native library.
+
+use std::ffi::CStr;
+use ws::{Endpoint, Mode, Settings, WsError};
+
+#[test]
+fn server_default_does_not_mask() {
+    let settings = Settings::default_for(Endpoint::Server);
+    assert_eq!(settings.endpoint(), Endpoint::Server);
+    assert!(!settings.auto_mask_frame());
+}
+
+#[test]
+fn client_default_masks() {
+    let settings = Settings::default_for(Endpoint::Client);
+    assert_eq!(settings.endpoint(), Endpoint::Client);
+    assert!(settings.auto_mask_frame());
+}
+
+#[test]
+fn server_rejects_masking() {
+    let result = Settings::builder()
+        .endpoint(Endpoint::Server)
+        .auto_mask_frame(true)
+        .build();
+    assert!(matches!(
+        result,
+        Err(WsError::InvalidSetting {
+            name: "auto_mask_frame",
+            ..
+        })
+    ));
+}
+
+#[test]
+fn window_bits_below_the_rfc_range_is_rejected() {
+    let result = Settings::builder().window_bits(7).build();
+    let Err(WsError::InvalidSetting { name, reason }) = result else {
+        panic!("window_bits(7) was accepted");
+    };
+    assert_eq!(name, "client_max_window_bits");
+    assert_eq!(reason, "7 is outside the RFC 7692 range 8..=15");
+}
+
+#[test]
+fn both_window_bits_are_validated_and_kept() {
+    let settings = Settings::builder()
+        .endpoint(Endpoint::Server)
+        .client_max_window_bits(9)
+        .server_max_window_bits(12)
+        .build()
+        .unwrap();
+    assert_eq!(settings.client_max_window_bits(), 9);
+    assert_eq!(settings.server_max_window_bits(), 12);
+
+    let result = Settings::builder().server_max_window_bits(16).build();
+    assert!(matches!(
+        result,
+        Err(WsError::InvalidSetting {
+            name: "server_max_window_bits",
+            ..
+        })
+    ));
+}
+
+fn rejected_host(host: &str) -> bool {
+    matches!(
+        Settings::builder()
+            .endpoint(Endpoint::Client)
+            .host(host)
+            .build(),
+        Err(WsError::InvalidSetting { name: "host", .. })
+    )
+}
+
+#[test]
+fn host_with_port_is_accepted() {
+    let settings = Settings::builder()
+        .endpoint(Endpoint::Client)
+        .host("localhost:4433")
+        .build()
+        .unwrap();
+    assert_eq!(settings.host(), Some("localhost:4433"));
+    assert_eq!(settings.port(), Some(4433));
+    assert_eq!(settings.mode(), Mode::Unsecured);
+    assert_eq!(settings.path(), None);
+}
+
+#[test]
+fn wss_url_selects_secured_mode_and_default_port() {
+    let settings = Settings::builder()
+        .endpoint(Endpoint::Client)
+        .host("wss://example.com")
+        .build()
+        .unwrap();
+    assert_eq!(settings.host(), Some("example.com"));
+    assert_eq!(settings.port(), Some(443));
+    assert_eq!(settings.mode(), Mode::Secured);
+    assert_eq!(settings.path(), Some("/"));
+}
+
+#[test]
+fn malformed_hosts_are_rejected() {
+    assert!(rejected_host("bad:port"));
+    assert!(rejected_host("localhost:0"));
+    assert!(rejected_host("localhost:65536"));
+    assert!(rejected_host("local\0host"));
+    assert!(rejected_host(":80"));
+}
+
+#[test]
+fn from_url_fills_in_the_scheme_default_port() {
+    let settings = Settings::from_url("ws://example.com/chat?room=1").unwrap();
+    assert_eq!(settings.mode(), Mode::Unsecured);
+    assert_eq!(settings.host(), Some("example.com"));
+    assert_eq!(settings.port(), Some(80));
+    assert_eq!(settings.path(), Some("/chat?room=1"));
+
+    let settings = Settings::try_from("wss://example.com").unwrap();
+    assert_eq!(settings.mode(), Mode::Secured);
+    assert_eq!(settings.port(), Some(443));
+    assert_eq!(settings.path(), Some("/"));
+
+    let settings = Settings::from_url("wss://[::1]:9000/chat").unwrap();
+    assert_eq!(settings.host(), Some("[::1]:9000"));
+    assert_eq!(settings.port(), Some(9000));
+    assert_eq!(settings.path(), Some("/chat"));
+}
+
+#[test]
+fn from_url_rejects_other_schemes() {
+    for url in ["http://example.com", "example.com:80", "ftp://example.com/"] {
+        assert!(
+            matches!(
+                Settings::from_url(url),
+                Err(WsError::InvalidSetting { name: "host", .. })
+            ),
+            "{url} was accepted"
+        );
+    }
+}
+
+fn ping_error(interval: u32, timeout: u32) -> Option<&'static str> {
+    match Settings::builder()
+        .ping_interval(interval)
+        .ping_timeout(timeout)
+        .build()
+    {
+        Ok(_) => None,
+        Err(WsError::InvalidSetting { name, .. }) => Some(name),
+        Err(e) => panic!("unexpected error: {e}"),
+    }
+}
+
+#[test]
+fn ping_timeout_must_be_shorter_than_the_interval() {
+    assert_eq!(ping_error(1000, 999), None);
+    assert_eq!(ping_error(1000, 1000), Some("ping_timeout"));
+    assert_eq!(ping_error(1000, 1001), Some("ping_timeout"));
+    assert_eq!(ping_error(2, 1), None);
+}
+
+#[test]
+fn zero_ping_values_are_rejected() {
+    assert_eq!(ping_error(0, 1), Some("ping_interval"));
+    assert_eq!(ping_error(1000, 0), Some("ping_timeout"));
+}
+
+/// The native struct only borrows the strings for the duration of
+/// `with_raw`; the `Settings` keeps ownership and frees them itself. Run
+/// under Miri or AddressSanitizer to check no pointer outlives the call.
+#[test]
+fn raw_strings_are_borrowed_for_the_call_only() {
+    let settings = Settings::builder()
+        .endpoint(Endpoint::Server)
+        .host("localhost:4433")
+        .allowed_origin("https://example.com")
+        .build()
+        .unwrap();
+    let read = |ptr: *const std::ffi::c_char| {
+        (!ptr.is_null()).then(|| unsafe { CStr::from_ptr(ptr) }.to_owned())
+    };
+    for _ in 0..2 {
+        let (host, origin, cert) = settings.with_raw(|raw| {
+            (
+                read(raw.host),
+                read(raw.allowed_origin),
+                read(raw.ssl_own_cert),
+            )
+        });
+        assert_eq!(host.as_deref(), Some(c"localhost:4433"));
+        assert_eq!(origin.as_deref(), Some(c"https://example.com"));
+        assert_eq!(cert, None);
+    }
+    drop(settings);
+}